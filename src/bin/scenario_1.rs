@@ -1,42 +1,151 @@
 use sp_domain::*;
-use sp_model::resources::plc::PLCResource;
+use sp_model::checks::check_interfaces;
+use sp_model::diff::{diff, ModelSummary};
+use sp_model::lab::{conveyor_handshakes, plc_tags, sim_config, PART_PRESENT};
+use sp_model::dot::{motions_dot, operations_dot, resources_dot, write_dot};
+use sp_model::parts::{PartTracker, OUTSIDE};
+use sp_model::record;
+use sp_model::report::model_report;
+use sp_model::storage::StorageGrid;
+use sp_model::resources::conveyor::Conveyor;
+use sp_model::resources::plc::{OpcNodeIds, PLCResource};
+use sp_model::resources::gripper::Gripper;
+use sp_model::resources::interface::RosInterface;
 use sp_model::resources::robotiq_gripper::RobotiqGripper;
-use sp_model::resources::frame_locker::FrameLocker;
+use sp_model::resources::frame_locker::{FrameLocker, LockServices};
 use sp_model::resources::ur::UrRobotResource;
+use sp_model::watchdog::{run_frame_watchdog, run_plc_watchdog, FrameWatchdog, PlcWatchdog};
 use sp_runner::*;
 
 // for convenience we just launch within this binary.
 #[tokio::main]
 async fn main() {
-    let (model, initial_state) = make_model();
+    // scenario_1 --dot <dir> writes graphs of the model instead.
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() == 3 && args[1] == "--dot" {
+        let (model, _, ur) = make_model_and_robot();
+        let dir = std::path::Path::new(&args[2]);
+        std::fs::create_dir_all(dir).unwrap();
+        write_dot(dir, "resources", &resources_dot(&model)).unwrap();
+        write_dot(dir, "operations", &operations_dot(&model)).unwrap();
+        write_dot(dir, "motions", &motions_dot(&ur.motions, &ur.last_visited_frame)).unwrap();
+        return;
+    }
+    // scenario_1 --report <file.md> writes a readable model report.
+    if args.len() == 3 && args[1] == "--report" {
+        let (model, initial_state) = make_model();
+        std::fs::write(&args[2], model_report(&model, &initial_state)).unwrap();
+        return;
+    }
+
+    // scenario_1 --export <file.json> saves a summary to diff against.
+    if args.len() == 3 && args[1] == "--export" {
+        let (model, _) = make_model();
+        std::fs::write(&args[2], ModelSummary::from_model(&model).to_json()).unwrap();
+        return;
+    }
+
+    // scenario_1 --diff <file.json> shows what changed since the export,
+    // and does not launch. run it before pushing a model to the lab.
+    if args.len() == 3 && args[1] == "--diff" {
+        let (model, _) = make_model();
+        let before = ModelSummary::from_json(&std::fs::read_to_string(&args[2]).unwrap()).unwrap();
+        println!("{}", diff(&before, &ModelSummary::from_model(&model)));
+        return;
+    }
+
+    // scenario_1 --replay <file> runs the model against a recording
+    // instead of the lab. the resources are simulated under /replay,
+    // away from the lab, so nothing else needs to be started.
+    if args.len() == 3 && args[1] == "--replay" {
+        let replay = RosInterface::with_namespace("replay");
+        let (mut model, initial_state, _) = make_model_on(&replay, ALL_PARTS);
+        let fired = record::log_fired(&mut model);
+        let entries = record::load(std::path::Path::new(&args[2])).unwrap();
+        record::run_replay(model, initial_state, fired, &entries, 1.0, replay, sim_config()).await.unwrap();
+        return;
+    }
+
+    // the watchdogs publish measurements for the resources, using the
+    // same topic names as the model.
+    let interface = interface();
+    tokio::spawn(run_plc_watchdog(PlcWatchdog {
+        measured_topic: interface.resolve("/opc_measured"),
+        connected_topic: interface.resolve("/opc_connected"),
+        timeout: std::time::Duration::from_secs(2),
+        heartbeat: None,
+        command_topic: interface.resolve("/opc_command"),
+        timeout_topic: interface.resolve("/opc_timeout"),
+        handshakes: conveyor_handshakes().iter()
+            .flat_map(|h| h.timer(&plc_tags(), &OpcNodeIds::default()))
+            .collect(),
+    }));
+    tokio::spawn(run_frame_watchdog(FrameWatchdog {
+        lookup_service: interface.resolve("/lookup_transform"),
+        fresh_topic: interface.resolve("/frame_fresh"),
+        parent_topic: interface.resolve("/frame_parent"),
+        frame_ids: vec!["aruco".into()],
+        ..Default::default()
+    }));
+
+    // scenario_1 --record <file> logs the runner state while running.
+    let (mut model, initial_state) = make_model();
+    if args.len() == 3 && args[1] == "--record" {
+        let fired = record::log_fired(&mut model);
+        let path = std::path::PathBuf::from(&args[2]);
+        tokio::spawn(async move { record::run_recorder(fired, "/sp/state", &path).await.unwrap() });
+    }
     launch_model(model, initial_state).await.unwrap();
 }
 
-// buffers p1-p4
-struct Buffer {
-    above_frame_name: String,
-    at_frame_name: String,
-    variable: SPPath
+fn interface() -> RosInterface {
+    RosInterface::default()
 }
 
+/// Enough cylinders to fill the buffers, the conveyor and the gripper.
+pub const ALL_PARTS: usize = 6;
+
 pub fn make_model() -> (Model, SPState) {
+    make_model_with(ALL_PARTS)
+}
+
+/// The model with only `part_count` cylinders, small enough to
+/// explore all of it.
+pub fn make_model_with(part_count: usize) -> (Model, SPState) {
+    let (m, initial_state, _) = make_model_on(&interface(), part_count);
+    (m, initial_state)
+}
+
+/// The model together with the robot, whose motions are kept for
+/// documentation.
+pub fn make_model_and_robot() -> (Model, SPState, UrRobotResource) {
+    make_model_on(&interface(), ALL_PARTS)
+}
+
+/// The model with `part_count` cylinders and its resources on
+/// `interface`, e.g. apart from the lab when replaying.
+pub fn make_model_on(interface: &RosInterface, part_count: usize) -> (Model, SPState, UrRobotResource) {
     let mut m = Model::new("lab_scenario_1");
 
-    let mut buffers = Vec::new();
-    buffers.push(Buffer { above_frame_name: "p1_above".into(), at_frame_name: "p1_down".into(),
-                          variable: m.add_product_bool("buffer_1") });
-    buffers.push(Buffer { above_frame_name: "p2_above".into(), at_frame_name: "p2_down".into(),
-                          variable: m.add_product_bool("buffer_2") });
-    buffers.push(Buffer { above_frame_name: "p3_above".into(), at_frame_name: "p3_down".into(),
-                          variable: m.add_product_bool("buffer_3") });
-    buffers.push(Buffer { above_frame_name: "p4_above".into(), at_frame_name: "p4_down".into(),
-                          variable: m.add_product_bool("buffer_4") });
+    // buffers p1-p4
+    let buffers = StorageGrid::new("buffer", "p", 1, 4);
 
-    // cylinder by end of conveyor
-    let cylinder_by_sensor = m.add_product_bool("cylinder_by_sensor");
+    // "conveyor" is by the sensor at the end of the conveyor, parts
+    // dropped at "drop_out" have left the cell.
+    let mut locations = vec!["conveyor", "gripper", "drop_out"];
+    locations.extend(buffers.locations());
+    let mut parts = PartTracker::new(&locations);
+    parts.add_bin("drop_out");
 
-    // cylinder in gripper
-    let cylinder_in_gripper = m.add_product_bool("cylinder_in_gripper");
+    let room = buffers.capacity() + 2; // and the conveyor and the gripper
+    assert!((1..=room).contains(&part_count), "between 1 and {} cylinders fit in the cell", room);
+    let cylinders: Vec<String> = (1..=part_count)
+        .map(|n| if n == 1 { "cylinder".to_string() } else { format!("cylinder_{}", n) })
+        .collect();
+    for c in &cylinders {
+        parts.add_part(&mut m, c, "cylinder");
+    }
+    parts.add_invariants(&mut m);
 
     let ur = m.add_resource("ur");
     let mut frames: Vec<SPValue> = ["home_pose",
@@ -49,27 +158,27 @@ pub fn make_model() -> (Model, SPState) {
         .iter()
         .map(|f| f.to_spvalue())
         .collect();
-    frames.extend(buffers
-        .iter()
-        .map(|f| f.at_frame_name.to_spvalue())
-        .collect::<Vec<_>>());
+    frames.extend(buffers.at_frames());
 
     let tool_frames: Vec<SPValue> = ["robotiq_2f_tcp", "tool0"]
         .iter()
         .map(|f| f.to_spvalue())
         .collect();
-    let mut ur = UrRobotResource::new(&mut m, &ur, frames, tool_frames);
+    let mut ur = UrRobotResource::new(&mut m, &ur, interface, frames, tool_frames);
 
     let gripper = m.add_resource("gripper");
-    let gripper = RobotiqGripper::new(m.get_resource(&gripper));
+    let gripper = RobotiqGripper::new(m.get_resource(&gripper), interface);
 
     let frame_locker = m.add_resource("frame_locker");
-    let frame_locker = FrameLocker::new(m.get_resource(&frame_locker));
+    let frame_locker = FrameLocker::new(m.get_resource(&frame_locker), interface,
+                                        &LockServices::default(), &["aruco"]);
+    let aruco = frame_locker.frame("aruco");
 
     let plc_path = m.add_resource("plc");
-    let d = vec![0.to_spvalue(), 1.to_spvalue(), 2.to_spvalue()];
-    let domain = [d.clone(), d.clone(), d.clone(), d.clone(), d.clone()];
-    let plc = PLCResource::new(m.get_resource(&plc_path), domain.clone(), domain.clone());
+    let plc_tags = plc_tags();
+    let plc = PLCResource::new(m.get_resource(&plc_path), interface,
+                               &OpcNodeIds::default(), &plc_tags);
+    plc.add_connection_invariants(&mut m, &plc_tags);
 
     let est_pos = ur.last_visited_frame.clone();
 
@@ -105,41 +214,27 @@ pub fn make_model() -> (Model, SPState) {
     ur.define_motion(&mut m, p!(est_pos == "placedown"), Predicate::TRUE, Predicate::TRUE,
                      "robotiq_2f_tcp", "home_pose", "move_j", 0.4, 0.3, vec![], vec![]);
 
-    // from any buffer, we can to to drop out
-    let guard = p!([est_pos == buffers[0].at_frame_name] || [est_pos == buffers[1].at_frame_name] ||
-                   [est_pos == buffers[2].at_frame_name] || [est_pos == buffers[3].at_frame_name]);
-    ur.define_motion(&mut m, guard, Predicate::TRUE, Predicate::TRUE,
-                     "robotiq_2f_tcp", "drop_out", "move_j", 0.4, 0.3, vec![], vec![]);
+    // from any buffer, we can go to drop out.
+    buffers.define_exit_motion(&mut ur, &mut m, "robotiq_2f_tcp", "drop_out");
 
     // from drop out to home
     ur.define_motion(&mut m, p!(est_pos == "drop_out"), Predicate::TRUE, Predicate::TRUE,
                      "robotiq_2f_tcp", "home_pose", "move_j", 0.4, 0.3, vec![], vec![]);
 
-    for bf in &buffers {
-        ur.define_motion(&mut m, p!(est_pos == "home_pose"), Predicate::TRUE, Predicate::TRUE,
-                         "robotiq_2f_tcp", &bf.at_frame_name, "move_j", 0.4, 0.3, vec![], vec![]);
-        ur.define_motion(&mut m, p!(est_pos == bf.at_frame_name), Predicate::TRUE, Predicate::TRUE,
-                         "robotiq_2f_tcp", "home_pose", "move_j", 0.4, 0.3, vec![], vec![]);
-    }
+    // the buffer frames are placed relative to the aruco, so we need a
+    // recent detection of it before moving down into them.
+    buffers.define_motions(&mut ur, &mut m, "robotiq_2f_tcp", "home_pose",
+                           &frame_locker.fresh("aruco"));
 
-    // can only grip in certain positions.
-    m.add_invar(
-        "grip_at_the_right_pos",
-        &p!([gripper.is_closing] => [[est_pos == "pickdown"] ||
-                                     [est_pos == "p1_down"] ||
-                                     [est_pos == "p2_down"] ||
-                                     [est_pos == "p3_down"] ||
-                                     [est_pos == "p4_down"]]),
-    );
-    m.add_invar(
-        "release_at_the_right_pos",
-        // todo: add all positions.
-        &p!([gripper.is_opening] => [[est_pos == "pickup"] ||
-                                     [est_pos == "placedown"] ||
-                                     [est_pos == "drop_out"] ||
-                                     [est_pos == "p1_down"] || [est_pos == "p2_down"] ||
-                                     [est_pos == "p3_down"] || [est_pos == "p4_down"]]),
-    );
+    // written against the Gripper trait so the end effector can be swapped.
+    let released = gripper.released();
+
+    // can only grip and release in certain positions.
+    // pickdown is needed to reopen after a missed pick.
+    // todo: add all positions.
+    buffers.add_gripper_invariants(&mut m, &gripper, &est_pos,
+                                   &["pickdown"],
+                                   &["pickup", "pickdown", "placedown", "drop_out"]);
 
     // m.add_invar("dont_open_gripper_after_failure",
     //             &p!([[gripper.is_opening] =>  [p:ap == t2] || [p:ap == t3] || [p:ap == leave]]] => [p:r1_gripper_part])
@@ -147,76 +242,46 @@ pub fn make_model() -> (Model, SPState) {
 
 
 
-    // PLC operations.
-    m.add_transition(Transition::new("start_load", p!([!plc.bool_to_plc_1]), Predicate::TRUE,
-                                vec![ a!(plc.bool_to_plc_1)], vec![], TransitionType::Controlled));
-    m.add_transition(Transition::new("finish_load",
-                                     p!([!plc.bool_from_plc_1] && [plc.bool_to_plc_1]), Predicate::TRUE,
-                                     vec![a!(plc.bool_from_plc_1)], vec![], TransitionType::Effect));
-    m.add_op(
-        "cylinder_to_sensor",
-        // operation model guard.
-        &p!([!cylinder_by_sensor] && [est_pos != "pickdown"]),
-        // operation model effects.
-        &[a!(cylinder_by_sensor)],
-        // low level goal
-        &p!(plc.bool_from_plc_1),
-        // low level actions
-        &[a!(!plc.bool_to_plc_1)],
-        // auto
-        false,
-        None,
-    );
+    // PLC operations. the plc program runs the conveyor itself.
+    let [load, unload] = conveyor_handshakes();
+    let conveyor = Conveyor::with_handshakes(&mut m, "conveyor", &plc, &load, &unload, PART_PRESENT);
 
-    m.add_transition(Transition::new("start_unload", p!(!plc.bool_to_plc_2), Predicate::TRUE,
-                                vec![ a!(plc.bool_to_plc_2)], vec![], TransitionType::Controlled));
-    m.add_transition(Transition::new("finish_unload",
-                                     p!([!plc.bool_from_plc_2] && [plc.bool_to_plc_2]), Predicate::TRUE,
-                                     vec![a!(plc.bool_from_plc_2)], vec![], TransitionType::Effect));
-    m.add_op(
-        "cylinder_from_sensor",
-        // operation model guard.
-        &p!([cylinder_by_sensor] && [est_pos != "pickdown"]),
-        // operation model effects.
-        &[a!(!cylinder_by_sensor)],
-        // low level goal
-        &p!(plc.bool_from_plc_2),
-        // low level actions
-        &[a!(!plc.bool_to_plc_2)],
-        // auto
-        false,
-        None,
-    );
+    // picks finish either with the part in the gripper or with the
+    // gripper closed empty, in which case the recovery transitions
+    // below undo the effects so that we can try again.
+    let holding_part = gripper.holding_part();
+    let part_missing = gripper.part_missing();
+    let grip_finished = p!([holding_part] || [part_missing]);
 
-    let _pick_at_conv_op = m.add_op(
-        "pick_at_conv",
-        &p!([!cylinder_in_gripper] && [cylinder_by_sensor] && [est_pos == "pickdown"]),
-        &[a!(cylinder_in_gripper), a!(!cylinder_by_sensor)],
-        &p!((gripper.measured) == "gripping"),
-        &[],
-        false,
-        None,
-    );
+    for c in &cylinders {
+        let cylinder = parts.part(c).location.clone();
+        let outside = parts.at(c, OUTSIDE);
+        let conveyor_free = parts.is_free("conveyor");
+        conveyor.add_to_sensor_op(
+            &mut m,
+            &parts.op_name("cylinder_to_sensor", c),
+            &p!([p: outside] && [p: conveyor_free] && [est_pos != "pickdown"]),
+            &[a!(cylinder <- "conveyor")]);
+        conveyor.add_from_sensor_op(
+            &mut m,
+            &parts.op_name("cylinder_from_sensor", c),
+            &p!([cylinder == "conveyor"] && [est_pos != "pickdown"]),
+            &[a!(cylinder <- OUTSIDE)]);
 
-    let _place_at_conv_op = m.add_op(
-        "place_at_conv",
-        &p!([cylinder_in_gripper] && [!cylinder_by_sensor] && [est_pos == "placedown"]),
-        &[a!(!cylinder_in_gripper), a!(cylinder_by_sensor)],
-        &p!((gripper.measured) == "opened"),
-        &[],
-        false,
-        None,
-    );
+        parts.add_move_op(&mut m, "pick_at_conv", c, "conveyor", "gripper",
+                          &p!(est_pos == "pickdown"), &grip_finished, &[]);
 
-    let _drop_at_drop_out = m.add_op(
-        "drop_at_drop_out",
-        &p!([cylinder_in_gripper] && [est_pos == "drop_out"]),
-        &[a!(!cylinder_in_gripper)],
-        &p!((gripper.measured) == "opened"),
-        &[],
-        false,
-        None,
-    );
+        gripper.add_part_missing_recovery(
+            &mut m, &parts.op_name("part_missing_at_conv", c),
+            p!([cylinder == "gripper"] && [est_pos == "pickdown"]),
+            vec![a!(cylinder <- "conveyor")]);
+
+        parts.add_move_op(&mut m, "place_at_conv", c, "gripper", "conveyor",
+                          &p!(est_pos == "placedown"), &p!(released), &[]);
+
+        parts.add_move_op(&mut m, "drop_at_drop_out", c, "gripper", "drop_out",
+                          &p!(est_pos == "drop_out"), &p!(released), &[]);
+    }
 
     // variable for alternating between find aruco poses.
     let looked_at_1 = m.add_estimated_bool("looked_at_1");
@@ -224,11 +289,11 @@ pub fn make_model() -> (Model, SPState) {
     // effect that says when we move towards an aruco finding pose, the aruco will show up
     m.add_transition(Transition::new(
         &format!("find_aruco_at_1"),
-        p!([!frame_locker.frame_exists] &&
+        p!([!aruco.exists] &&
            [(ur.trigger)] && [!(ur.done)] &&
            [[(ur.goal_feature_name) == "find_aruco_1"] || [(ur.goal_feature_name) == "find_aruco_2"]]),
         Predicate::TRUE,
-        vec![a!(frame_locker.frame_exists)],
+        vec![a!(aruco.exists)],
         vec![],
         TransitionType::Effect));
 
@@ -252,7 +317,7 @@ pub fn make_model() -> (Model, SPState) {
     // m.add_invar(
     //     "lock_at_the_right_pos",
     //     // todo: add all positions.
-    //     &p!([frame_locker.is_locking] => [[est_pos == "find_aruco_1"] || [est_pos == "find_aruco_2"]])
+    //     &p!([aruco.is_locking] => [[est_pos == "find_aruco_1"] || [est_pos == "find_aruco_2"]])
     // );
 
     let aruco_locked = m.add_product_bool("aruco_locked");
@@ -260,41 +325,34 @@ pub fn make_model() -> (Model, SPState) {
         "lock_aruco",
         &p!(!aruco_locked),
         &[a!(aruco_locked)],
-        &p!([frame_locker.frame_locked]),
+        &p!([aruco.locked]),
         &[],
         true,
         None,
     );
 
-    // convenience reset of product variable
-    m.add_transition(Transition::new(
-        &format!("reset_locked_aruco"),
-        p!([aruco_locked] && [!frame_locker.frame_locked]),
-        Predicate::TRUE,
-        vec![a!(!aruco_locked)],
-        vec![],
-        TransitionType::Runner));
-
-    for b in &buffers {
-        let _place_op = m.add_op(
-            &format!("place_at_{}", b.variable.leaf()),
-            &p!([cylinder_in_gripper] && [aruco_locked] && [!b.variable] && [est_pos == b.at_frame_name]),
-            &[a!(!cylinder_in_gripper), a!(b.variable)],
-            &p!([(gripper.measured) == "opened"]),
-            &[],
-            false,
-            None,
-        );
-
-        let _pick_op = m.add_op(
-            &format!("pick_at_{}", b.variable.leaf()),
-            &p!([!cylinder_in_gripper] && [aruco_locked] && [b.variable] && [est_pos == b.at_frame_name]),
-            &[a!(cylinder_in_gripper), a!(!b.variable)],
-            &p!((gripper.measured) == "gripping"),
-            &[],
-            false,
-            None,
-        );
+    // release the aruco so that it can be detected again
+    let _unlock_aruco_op = m.add_op(
+        "unlock_aruco",
+        &p!(aruco_locked),
+        &[a!(!aruco_locked)],
+        &p!([!aruco.locked]),
+        &[],
+        false,
+        None,
+    );
+
+    for c in &cylinders {
+        buffers.add_operations(&mut m, &parts, c, "gripper", &est_pos,
+                               &p!(aruco_locked), &p!(released), &grip_finished);
+
+        let cylinder = parts.part(c).location.clone();
+        for b in &buffers.slots {
+            gripper.add_part_missing_recovery(
+                &mut m, &parts.op_name(&format!("part_missing_at_{}", b.location), c),
+                p!([cylinder == "gripper"] && [est_pos == b.at_frame]),
+                vec![a!(cylinder <- b.location)]);
+        }
     }
 
     // This intention is updated by the GUI.
@@ -310,27 +368,26 @@ pub fn make_model() -> (Model, SPState) {
         "clear_scene",
         false,
         &Predicate::FALSE,
-        &p!([!cylinder_in_gripper] && [!cylinder_by_sensor] &&
-            [! buffers[0].variable] && [! buffers[1].variable] &&
-            [! buffers[2].variable] && [! buffers[3].variable]),
+        &parts.all_outside(),
         &[],
     );
 
+    if let Err(report) = check_interfaces(&m) {
+        panic!("{}", report);
+    }
+
     let mut initial_state = ur.initial_state.clone();
     initial_state.extend(plc.initial_state);
-    initial_state.extend(gripper.initial_state);
+    initial_state.extend(conveyor.initial_state);
+    initial_state.extend(gripper.initial_state().clone());
     initial_state.extend(frame_locker.initial_state);
 
-    let buffer_states: Vec<_> =
-        buffers.iter().map(|b| (b.variable.clone(), false.to_spvalue())).collect();
-    initial_state.extend(SPState::new_from_values(&buffer_states));
+    initial_state.extend(parts.initial_state());
 
     initial_state.extend(SPState::new_from_values(
         &[
             (looked_at_1, false.to_spvalue()),
             (est_pos, "unknown".to_spvalue()),
-            (cylinder_by_sensor, false.to_spvalue()),
-            (cylinder_in_gripper, false.to_spvalue()),
             (aruco_locked, false.to_spvalue()),
         ]
     ));
@@ -351,12 +408,39 @@ pub fn make_model() -> (Model, SPState) {
         .collect::<Vec<_>>();
     initial_state.extend(SPState::new_from_values(intention_state.as_slice()));
 
-    return (m, initial_state);
+    return (m, initial_state, ur);
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use sp_model::diff::{diff_models, Change};
+    use sp_model::fuzz::fuzz;
+    use sp_model::harness::{run_intention, run_intention_simulated};
+    use sp_model::sim::{frame_locker::SimFrameLocker, SimConfig};
+    use sp_model::plan_check::*;
+    use sp_model::verify::{check_liveness, verify};
+    use std::time::Duration;
+
+    /// The model in the state the runner starts in with the hardware
+    /// up: the gripper open, the PLC connected and the services idle.
+    fn initial_model() -> (Model, SPState) {
+        initial_model_with(ALL_PARTS)
+    }
+
+    fn initial_model_with(part_count: usize) -> (Model, SPState) {
+        let (m, mut s) = make_model_with(part_count);
+        let gripper_state = SPPath::from_string("lab_scenario_1/gripper/measured");
+        s.add_variable(gripper_state, "opened".to_spvalue());
+        for service in &["gripper/robotiq_2f_open", "gripper/robotiq_2f_close",
+                         "frame_locker/lock_frames", "frame_locker/unlock_frames"] {
+            let path = SPPath::from_string(&format!("lab_scenario_1/{}", service));
+            s.add_variable(path, "ok".to_spvalue());
+        }
+        let connected = SPPath::from_string("lab_scenario_1/plc/measured/plc_connected");
+        s.add_variable(connected, true.to_spvalue());
+        (m, s)
+    }
 
     #[test]
     fn modelcheck() {
@@ -368,17 +452,126 @@ mod test {
     }
 
     #[test]
-    fn plan() {
-        let (m, mut s) = make_model();
+    fn invariants_hold() {
+        let (m, s) = initial_model();
         let ts_model = TransitionSystemModel::from(&m);
 
-        // make a goal
-        let op_done = SPPath::from_string("lab_scenario_1/product_state/op_done");
-        let goal = [(p!(op_done), None)];
+        // too many parts to explore it all, but what is found holds.
+        let report = verify(&ts_model, &s, 200_000);
+        println!("{}", report);
+        assert!(report.ok(), "{}", report);
+    }
 
-        // set initial state
-        let gripper_state = SPPath::from_string("lab_scenario_1/gripper/measured");
-        s.add_variable(gripper_state, "opened".to_spvalue());
+    #[test]
+    fn invariants_hold_with_two_parts() {
+        let (m, s) = initial_model_with(2);
+        let ts_model = TransitionSystemModel::from(&m);
+
+        let report = verify(&ts_model, &s, 200_000);
+        println!("{}", report);
+        assert!(report.complete, "{}", report);
+        assert!(report.ok(), "{}", report);
+    }
+
+    #[test]
+    fn cannot_get_stuck() {
+        // liveness needs the whole graph, which only one part keeps
+        // small enough.
+        let (m, s) = initial_model_with(1);
+
+        let report = check_liveness(&m, &s, 200_000, 3);
+        println!("{}", report);
+        assert!(report.complete, "{}", report);
+        assert!(report.ok(), "{}", report);
+    }
+
+    #[test]
+    fn random_walks_keep_invariants() {
+        let (m, s) = initial_model();
+        let ts_model = TransitionSystemModel::from(&m);
+
+        if let Err(failure) = fuzz(&ts_model, &s, 1, 200, 100) {
+            panic!("{}", failure);
+        }
+    }
+
+    #[test]
+    fn motion_graph() {
+        let (_m, _s, ur) = make_model_and_robot();
+        let dot = motions_dot(&ur.motions, &ur.last_visited_frame);
+        assert!(dot.contains("\"home_pose\" -> \"pickup\""), "{}", dot);
+        assert!(dot.contains("\"p3_down\" -> \"drop_out\""), "{}", dot);
+    }
+
+    #[test]
+    fn report_links_variables() {
+        let (m, s) = make_model();
+        let report = model_report(&m, &s);
+        assert!(report.contains("## Operations"));
+        assert!(report.contains("(#lab_scenario_1-gripper-measured)"), "{}", report);
+        // the part locations are read by the goal of clear_scene.
+        assert!(report.contains("[clear_scene](#intention-"), "{}", report);
+    }
+
+    #[test]
+    fn diff_shows_added_invariant() {
+        let (before, _) = make_model();
+        let (mut after, _) = make_model();
+        after.add_invar("never_at_pickdown", &Predicate::FALSE);
+
+        let json = ModelSummary::from_model(&before).to_json();
+        let exported = ModelSummary::from_json(&json).unwrap();
+        assert_eq!(exported, ModelSummary::from_model(&make_model().0));
+
+        let changes = diff_models(&before, &after);
+        assert_eq!(changes.changes.len(), 1, "{}", changes);
+        match &changes.changes[0] {
+            Change::Added { category, after, .. } => {
+                assert_eq!(category, "invariant");
+                assert_eq!(after["invariant"], Predicate::FALSE.to_string());
+            }
+            c => panic!("expected an added invariant, got {:?}", c),
+        }
+        assert_eq!(diff(&exported, &ModelSummary::from_model(&after)).changes, changes.changes);
+    }
+
+    #[test]
+    fn recording_explains_fired_transitions() {
+        let (m, s) = initial_model();
+        let ts = TransitionSystemModel::from(&m);
+
+        // take one step of the harness and see that we can tell what happened.
+        let t = ts.transitions.iter()
+            .find(|t| t.type_ == TransitionType::Controlled && t.eval(&s))
+            .unwrap();
+        let mut next = s.clone();
+        for a in t.actions() {
+            a.next(&mut next).unwrap();
+        }
+        next.take_transition();
+        assert!(record::explain(&ts, &s, &next).contains(&t.path().to_string()));
+    }
+
+    #[test]
+    fn runner_logs_fired_transitions() {
+        let (mut m, _) = make_model();
+        let fired = record::log_fired(&mut m);
+        let logged = m.resources.iter()
+            .flat_map(|r| r.transitions.iter())
+            .filter(|t| t.type_ != TransitionType::Effect);
+        for t in logged {
+            assert!(t.runner_actions.iter().any(|a| a.var == fired), "{} is not logged", t.path());
+        }
+    }
+
+    #[test]
+    fn plan() {
+        let (m, s) = initial_model();
+        let ts_model = TransitionSystemModel::from_op(&m);
+
+        // make a goal
+        let cylinder = SPPath::from_string("lab_scenario_1/product_state/cylinder_location");
+        let goal = [(p!(cylinder == "buffer_3"), None)];
 
         let plan = sp_formal::planning::plan(&ts_model, &goal, &s, 100);
         match plan {
@@ -395,4 +588,113 @@ mod test {
             }
         };
     }
+
+    #[test]
+    fn every_operation_has_a_plan() {
+        let (m, s) = initial_model();
+        let ts_model = TransitionSystemModel::from_op(&m);
+
+        let report = check_plans(&ts_model, &s, &operation_goals(&ts_model), 30);
+        println!("OPERATIONS\n{}", report);
+        assert!(report.unreachable().is_empty(), "unreachable: {:?}", report.unreachable());
+    }
+
+    #[test]
+    fn every_intention_has_a_plan() {
+        let (m, s) = initial_model();
+        let ts_model = TransitionSystemModel::from_op(&m);
+
+        // start with a cylinder in the cell so that clearing it takes some work.
+        let mut s = s;
+        let cylinder = SPPath::from_string("lab_scenario_1/product_state/cylinder_location");
+        s.add_variable(cylinder, "conveyor".to_spvalue());
+
+        let report = check_plans(&ts_model, &s, &intention_goals(&m), 30);
+        println!("INTENTIONS\n{}", report);
+        assert!(report.unreachable().is_empty(), "unreachable: {:?}", report.unreachable());
+    }
+
+    #[test]
+    fn every_operation_can_finish() {
+        let (m, s) = initial_model();
+
+        let report = check_completions(&m, &s, 500_000);
+        println!("COMPLETIONS\n{}", report);
+        // a completion found holds, a partial search only leaves the
+        // missing ones open.
+        let partial = if report.complete { "" } else { " within the search limit" };
+        assert!(report.unreachable().is_empty(), "cannot finish{}: {:?}", partial, report.unreachable());
+    }
+
+    #[test]
+    fn unreachable_precondition_has_no_plan() {
+        // without the lock op the aruco is never locked, and nothing
+        // can be placed in or picked from the buffers.
+        let (mut m, s) = make_model();
+        m.operations.retain(|o| o.path().leaf() != "lock_aruco");
+        let ts_model = TransitionSystemModel::from_op(&m);
+
+        let goals: Vec<_> = operation_goals(&ts_model)
+            .into_iter()
+            .filter(|(name, _)| name.starts_with("pick_at_buffer") || name.starts_with("place_at_buffer"))
+            .collect();
+        assert!(!goals.is_empty());
+
+        let report = check_plans(&ts_model, &s, &goals, 30);
+        println!("{}", report);
+        assert_eq!(report.unreachable().len(), goals.len());
+    }
+
+    #[test]
+    fn clear_scene_from_buffer() {
+        let (m, mut s) = make_model();
+
+        // a cylinder left in a buffer
+        let cylinder = SPPath::from_string("lab_scenario_1/product_state/cylinder_location");
+        s.add_variable(cylinder, "buffer_2".to_spvalue());
+        let aruco_locked = SPPath::from_string("lab_scenario_1/product_state/aruco_locked");
+        s.add_variable(aruco_locked, true.to_spvalue());
+
+        match run_intention(&m, &s, "clear_scene", 50, Duration::from_secs(30)) {
+            Err(failure) => panic!("{}", failure),
+            Ok(trace) => {
+                println!("CLEARED THE SCENE");
+                for t in &trace {
+                    println!("{}", t);
+                }
+            }
+        }
+    }
+
+    // end to end on the runner with simulated resources. needs a ros
+    // environment, run with cargo test -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn clear_scene_simulated() {
+        let (m, mut s) = make_model();
+
+        let cylinder = SPPath::from_string("lab_scenario_1/product_state/cylinder_location");
+        s.add_variable(cylinder, "buffer_2".to_spvalue());
+
+        // the buffer motions need a fresh aruco.
+        tokio::spawn(run_frame_watchdog(FrameWatchdog {
+            frame_ids: vec!["aruco".into()],
+            ..Default::default()
+        }));
+        let sim = SimConfig {
+            frame_locker: SimFrameLocker {
+                frame_ids: vec!["aruco".into()],
+            },
+            ..Default::default()
+        };
+        match run_intention_simulated(m, s, "clear_scene", interface(), sim, Duration::from_secs(120)).await {
+            Err(failure) => panic!("{}", failure),
+            Ok(trace) => {
+                println!("CLEARED THE SCENE");
+                for t in &trace {
+                    println!("{}", t);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,9 @@
+use sp_model::lab::sim_config;
+use sp_model::resources::interface::RosInterface;
+use sp_model::sim::*;
+
+// simulated hardware for scenario_1. run next to it for a dry run.
+#[tokio::main]
+async fn main() {
+    run_simulation(&RosInterface::default(), sim_config()).await.unwrap();
+}
@@ -0,0 +1,157 @@
+use sp_domain::*;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Problems found in how the resources of a model are wired to ROS.
+#[derive(Debug, Default)]
+pub struct InterfaceReport {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for InterfaceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} interface problem(s):", self.problems.len())?;
+        for p in &self.problems {
+            writeln!(f, "  - {}", p)?;
+        }
+        Ok(())
+    }
+}
+
+/// Scan all resources for
+/// - messages sharing a name within the same resource,
+/// - topics bound to different variables by different messages, or
+///   services called from different resources,
+/// - variables written by more than one incoming message.
+pub fn check_interfaces(m: &Model) -> Result<(), InterfaceReport> {
+    let mut report = InterfaceReport::default();
+
+    // topic -> (message, bound variables and caller)
+    let mut topics: HashMap<String, (String, Vec<String>)> = HashMap::new();
+    // variable -> incoming message writing it
+    let mut writers: HashMap<String, String> = HashMap::new();
+
+    for r in &m.resources {
+        let mut names: HashMap<String, usize> = HashMap::new();
+        for msg in &r.messages {
+            let name = msg.name.to_string();
+            *names.entry(name.clone()).or_insert(0) += 1;
+
+            let mut variables: Vec<String> = msg.variables.iter().map(|v| v.path.to_string()).collect();
+            variables.sort();
+
+            // a service call is bound to the resource triggering it, so
+            // the same service may only be called twice from one
+            // resource with the same request.
+            let mut binding = variables.clone();
+            if msg.category == MessageCategory::Service {
+                binding.push(format!("(called by {})", r.path()));
+            }
+
+            let topic = msg.topic.to_string();
+            match topics.get(&topic) {
+                Some((other, other_binding)) if other_binding != &binding => {
+                    report.problems.push(format!(
+                        "topic {} is bound to different variables by {} and {}",
+                        topic, other, name));
+                }
+                Some(_) => {}
+                None => {
+                    topics.insert(topic, (name.clone(), binding));
+                }
+            }
+
+            if msg.category == MessageCategory::Incoming {
+                for v in &variables {
+                    if let Some(other) = writers.insert(v.clone(), name.clone()) {
+                        report.problems.push(format!(
+                            "variable {} is written by both {} and {}",
+                            v, other, name));
+                    }
+                }
+            }
+        }
+
+        let mut duplicates: Vec<_> = names.into_iter().filter(|(_, n)| *n > 1).collect();
+        duplicates.sort();
+        for (name, n) in duplicates {
+            report.problems.push(format!(
+                "message name {} is used {} times in {}", name, n, r.path()));
+        }
+    }
+
+    if report.problems.is_empty() {
+        Ok(())
+    } else {
+        Err(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bool_in(r: &mut Resource, name: &str, topic: &str, variable: &SPPath) {
+        r.setup_ros_incoming(name, topic, MessageType::Ros("std_msgs/msg/Bool".into()),
+                             &[MessageVariable::new(variable, "data")]);
+    }
+
+    #[test]
+    fn clean_model_passes() {
+        let mut m = Model::new("test");
+        let r = m.add_resource("sensor");
+        let r = m.get_resource(&r);
+        let a = r.add_variable(Variable::new_boolean("a", VariableType::Measured));
+        let b = r.add_variable(Variable::new_boolean("b", VariableType::Measured));
+        bool_in(r, "a", "/a", &a);
+        bool_in(r, "b", "/b", &b);
+        assert!(check_interfaces(&m).is_ok());
+    }
+
+    #[test]
+    fn clashes_are_reported() {
+        let mut m = Model::new("test");
+        let r = m.add_resource("sensor");
+        let r = m.get_resource(&r);
+        let a = r.add_variable(Variable::new_boolean("a", VariableType::Measured));
+        let b = r.add_variable(Variable::new_boolean("b", VariableType::Measured));
+        // the same name twice, and /a bound to both a and b.
+        bool_in(r, "a", "/a", &a);
+        bool_in(r, "a", "/a", &b);
+
+        let report = check_interfaces(&m).unwrap_err();
+        assert_eq!(report.problems.len(), 2, "{}", report);
+        assert!(report.problems.iter().any(|p| p.starts_with("message name a is used 2 times")));
+        assert!(report.problems.iter().any(|p| p.starts_with("topic /a is bound to different variables")));
+    }
+
+    #[test]
+    fn two_writers_are_reported() {
+        let mut m = Model::new("test");
+        let r = m.add_resource("sensor");
+        let r = m.get_resource(&r);
+        let a = r.add_variable(Variable::new_boolean("a", VariableType::Measured));
+        bool_in(r, "a", "/a", &a);
+        bool_in(r, "a_again", "/a_again", &a);
+
+        let report = check_interfaces(&m).unwrap_err();
+        assert_eq!(report.problems.len(), 1, "{}", report);
+        assert!(report.problems[0].contains("written by both"));
+    }
+
+    #[test]
+    fn service_shared_by_two_resources_is_reported() {
+        let mut m = Model::new("test");
+        for gripper in &["left", "right"] {
+            let r = m.add_resource(*gripper);
+            let r = m.get_resource(&r);
+            let open = r.add_variable(Variable::new_boolean("open", VariableType::Command));
+            r.setup_ros_service("open", "/robotiq_2f_open", "std_srvs/srv/Trigger",
+                                p!(open), &[], &[]);
+        }
+
+        let report = check_interfaces(&m).unwrap_err();
+        assert_eq!(report.problems.len(), 1, "{}", report);
+        assert!(report.problems[0].starts_with("topic /robotiq_2f_open"));
+    }
+}
@@ -0,0 +1,203 @@
+use sp_domain::*;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The fields of one model entry, e.g. the guard and actions of a
+/// transition, each described as a string.
+pub type Fields = BTreeMap<String, String>;
+
+/// The parts of a model that matter for its behavior, by category
+/// and name. Can be exported to json to compare against a later
+/// version of the model.
+#[derive(Debug, Default, PartialEq)]
+pub struct ModelSummary {
+    pub entries: BTreeMap<String, BTreeMap<String, Fields>>,
+}
+
+fn joined<T: fmt::Display>(items: &[T]) -> String {
+    items.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+impl ModelSummary {
+    pub fn from_model(m: &Model) -> ModelSummary {
+        let ts = TransitionSystemModel::from(m);
+        let ops = TransitionSystemModel::from_op(m);
+        let mut summary = ModelSummary::default();
+
+        for v in &ts.vars {
+            summary.add("variable", &v.path().to_string(), &[
+                ("type", format!("{:?}", v.variable_type())),
+                ("value type", format!("{:?}", v.value_type())),
+                ("domain", joined(v.domain())),
+            ]);
+        }
+        for t in &ts.transitions {
+            summary.add("transition", &t.path().to_string(), &[
+                ("type", format!("{:?}", t.type_)),
+                ("guard", t.guard().to_string()),
+                ("actions", joined(t.actions())),
+            ]);
+        }
+        for o in &m.operations {
+            let high_level = ops.transitions.iter().find(|t| t.path().leaf() == o.path().leaf());
+            let (pre, effects) = match high_level {
+                Some(t) => (t.guard().to_string(), joined(t.actions())),
+                None => (String::new(), String::new()),
+            };
+            summary.add("operation", &o.path().to_string(), &[
+                ("pre", pre),
+                ("effects", effects),
+                ("goal", o.goal().to_string()),
+            ]);
+        }
+        for s in &ts.specs {
+            summary.add("invariant", &s.path().to_string(), &[
+                ("invariant", s.invariant().to_string()),
+            ]);
+        }
+        for i in &m.intentions {
+            summary.add("intention", &i.path().to_string(), &[
+                ("goal", i.goal().to_string()),
+            ]);
+        }
+        summary
+    }
+
+    fn add(&mut self, category: &str, name: &str, fields: &[(&str, String)]) {
+        let fields = fields.iter().map(|(f, v)| (f.to_string(), v.clone())).collect();
+        self.entries.entry(category.into()).or_default().insert(name.into(), fields);
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.entries).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> SPResult<ModelSummary> {
+        let entries = serde_json::from_str(json).map_err(SPError::from_any)?;
+        Ok(ModelSummary { entries })
+    }
+}
+
+/// One field of a changed entry, e.g. the guard of a transition.
+#[derive(Debug, PartialEq)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: String,
+    pub after: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    Added { category: String, name: String, after: Fields },
+    Removed { category: String, name: String, before: Fields },
+    Changed { category: String, name: String, fields: Vec<FieldChange> },
+}
+
+impl Change {
+    pub fn category(&self) -> &str {
+        match self {
+            Change::Added { category, .. } => category,
+            Change::Removed { category, .. } => category,
+            Change::Changed { category, .. } => category,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ModelDiff {
+    pub changes: Vec<Change>,
+}
+
+impl ModelDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The changes to e.g. the invariants.
+    pub fn in_category<'a>(&'a self, category: &'a str) -> impl Iterator<Item = &'a Change> + 'a {
+        self.changes.iter().filter(move |c| c.category() == category)
+    }
+}
+
+fn write_fields(f: &mut fmt::Formatter<'_>, fields: &Fields) -> fmt::Result {
+    for (field, value) in fields {
+        writeln!(f, "    {}: {}", field, value)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for ModelDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.changes.is_empty() {
+            return writeln!(f, "no changes");
+        }
+        for c in &self.changes {
+            match c {
+                Change::Added { category, name, after } => {
+                    writeln!(f, "+ {} {}", category, name)?;
+                    write_fields(f, after)?;
+                }
+                Change::Removed { category, name, before } => {
+                    writeln!(f, "- {} {}", category, name)?;
+                    write_fields(f, before)?;
+                }
+                Change::Changed { category, name, fields } => {
+                    writeln!(f, "~ {} {}", category, name)?;
+                    for c in fields {
+                        writeln!(f, "    {} was: {}\n    {} now: {}", c.field, c.before, c.field, c.after)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The fields that differ, a field missing on one side is empty.
+fn changed_fields(before: &Fields, after: &Fields) -> Vec<FieldChange> {
+    let mut names: Vec<&String> = before.keys().chain(after.keys()).collect();
+    names.sort();
+    names.dedup();
+    names.into_iter()
+        .map(|name| FieldChange {
+            field: name.clone(),
+            before: before.get(name).cloned().unwrap_or_default(),
+            after: after.get(name).cloned().unwrap_or_default(),
+        })
+        .filter(|c| c.before != c.after)
+        .collect()
+}
+
+/// What changed going from `before` to `after`, ordered by category
+/// and name.
+pub fn diff(before: &ModelSummary, after: &ModelSummary) -> ModelDiff {
+    let empty = BTreeMap::new();
+    let mut categories: Vec<&String> = before.entries.keys().chain(after.entries.keys()).collect();
+    categories.sort();
+    categories.dedup();
+
+    let mut changes = vec![];
+    for category in categories {
+        let b = before.entries.get(category).unwrap_or(&empty);
+        let a = after.entries.get(category).unwrap_or(&empty);
+        for (name, old) in b {
+            match a.get(name) {
+                None => changes.push(Change::Removed {
+                    category: category.clone(), name: name.clone(), before: old.clone() }),
+                Some(new) if new != old => changes.push(Change::Changed {
+                    category: category.clone(), name: name.clone(),
+                    fields: changed_fields(old, new) }),
+                Some(_) => {}
+            }
+        }
+        for (name, new) in a.iter().filter(|(name, _)| !b.contains_key(*name)) {
+            changes.push(Change::Added {
+                category: category.clone(), name: name.clone(), after: new.clone() });
+        }
+    }
+    ModelDiff { changes }
+}
+
+pub fn diff_models(before: &Model, after: &Model) -> ModelDiff {
+    diff(&ModelSummary::from_model(before), &ModelSummary::from_model(after))
+}
@@ -0,0 +1,114 @@
+use crate::resources::ur::Motion;
+use sp_domain::*;
+use std::fmt::Write;
+
+fn color(t: &TransitionType) -> &'static str {
+    match t {
+        TransitionType::Controlled => "blue",
+        TransitionType::Effect => "orange",
+        TransitionType::Auto => "darkgreen",
+        TransitionType::Runner => "gray",
+    }
+}
+
+fn quote(s: &str) -> String {
+    let escaped = s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+fn written(actions: &[Action]) -> Vec<&SPPath> {
+    actions.iter().map(|a| &a.var).collect()
+}
+
+/// Transitions as nodes, with an edge from t1 to t2 when t1 writes a
+/// variable that the guard of t2 reads.
+fn transition_graph(out: &mut String, transitions: &[&Transition], prefix: &str) {
+    for (i, t) in transitions.iter().enumerate() {
+        let label = format!("{}\n{}", t.path().leaf(), t.guard());
+        writeln!(out, "  {}{} [label={}, color={}, fontcolor={}];",
+                 prefix, i, quote(&label), color(&t.type_), color(&t.type_)).unwrap();
+    }
+    for (i, from) in transitions.iter().enumerate() {
+        let writes = written(from.actions());
+        for (j, to) in transitions.iter().enumerate() {
+            if i != j && to.guard().support().iter().any(|v| writes.contains(&v)) {
+                writeln!(out, "  {}{} -> {}{};", prefix, i, prefix, j).unwrap();
+            }
+        }
+    }
+}
+
+/// One cluster per resource with its transitions, colored by type:
+/// controlled blue, effect orange, auto green and runner gray.
+pub fn resources_dot(m: &Model) -> String {
+    let mut out = String::from("digraph resources {\n  node [shape=box];\n");
+    for (r_index, r) in m.resources.iter().enumerate() {
+        writeln!(out, "  subgraph cluster_{} {{\n  label={};", r_index, quote(&r.path().leaf())).unwrap();
+        let transitions: Vec<&Transition> = r.transitions.iter().collect();
+        transition_graph(&mut out, &transitions, &format!("r{}_", r_index));
+        out.push_str("  }\n");
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// The operations, with an edge from o1 to o2 when the effects of o1
+/// touch the precondition of o2.
+pub fn operations_dot(m: &Model) -> String {
+    let ts = TransitionSystemModel::from_op(m);
+    let ops: Vec<&Transition> = ts.transitions.iter().collect();
+    let mut out = String::from("digraph operations {\n  node [shape=box];\n");
+    transition_graph(&mut out, &ops, "o");
+    out.push_str("}\n");
+    out
+}
+
+/// Frames that `guard` requires `frame` to be equal to. Only looks
+/// at equalities under AND/OR, which is how motion guards are
+/// written.
+fn frames_in(guard: &Predicate, frame: &SPPath, frames: &mut Vec<String>) {
+    match guard {
+        Predicate::AND(ps) | Predicate::OR(ps) => ps.iter().for_each(|p| frames_in(p, frame, frames)),
+        Predicate::EQ(PredicateValue::SPPath(p, ..), PredicateValue::SPValue(v))
+        | Predicate::EQ(PredicateValue::SPValue(v), PredicateValue::SPPath(p, ..)) if p == frame => {
+            frames.push(v.to_string())
+        }
+        _ => {}
+    }
+}
+
+/// The frame graph of the robot: an edge for every motion from the
+/// frames it can start in to its goal frame. Motions that do not
+/// constrain the start frame start from "*".
+pub fn motions_dot(motions: &[Motion], last_visited_frame: &SPPath) -> String {
+    let mut out = String::from("digraph motions {\n");
+    for m in motions {
+        let mut from = vec![];
+        frames_in(&m.guard, last_visited_frame, &mut from);
+        if from.is_empty() {
+            from.push("*".into());
+        }
+        for f in from {
+            writeln!(out, "  {} -> {} [label={}];", quote(&f), quote(&m.goal_frame), quote(&m.tcp_frame)).unwrap();
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Write `<dir>/<name>.dot`, and `<name>.svg` too if graphviz is
+/// installed.
+pub fn write_dot(dir: &std::path::Path, name: &str, dot: &str) -> std::io::Result<()> {
+    let path = dir.join(format!("{}.dot", name));
+    std::fs::write(&path, dot)?;
+    let svg = std::process::Command::new("dot")
+        .arg("-Tsvg")
+        .arg(&path)
+        .arg("-o")
+        .arg(dir.join(format!("{}.svg", name)))
+        .status();
+    if let Err(e) = svg {
+        println!("not rendering {}.svg, graphviz not found: {}", name, e);
+    }
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use crate::harness::fire;
+use crate::verify::formal_transitions;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use sp_domain::*;
+use std::fmt;
+
+/// A random walk that broke a property, shrunk to as few
+/// transitions as we could manage.
+#[derive(Debug)]
+pub struct FuzzFailure {
+    pub seed: u64,
+    pub property: String,
+    pub trace: Vec<String>,
+}
+
+impl fmt::Display for FuzzFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} violated (seed {}) after {} transition(s):",
+                 self.property, self.seed, self.trace.len())?;
+        for (i, t) in self.trace.iter().enumerate() {
+            writeln!(f, "  {:3}: {}", i, t)?;
+        }
+        Ok(())
+    }
+}
+
+/// The first invariant or variable domain that does not hold in `state`.
+fn violated(ts: &TransitionSystemModel, state: &SPState) -> Option<String> {
+    if let Some(spec) = ts.specs.iter().find(|s| !s.invariant().eval(state)) {
+        return Some(spec.path().to_string());
+    }
+    ts.vars.iter().find_map(|v| {
+        let domain = v.domain();
+        match state.sp_value_from_path(v.path()) {
+            Some(value) if !domain.is_empty() && !domain.contains(value) =>
+                Some(format!("domain of {} ({} is not in it)", v.path(), value)),
+            _ => None,
+        }
+    })
+}
+
+/// Whether `t` may fire in `state`: enabled, and for a controlled
+/// transition, not leading to a state that breaks an invariant.
+/// Effects and auto transitions fire regardless. This only looks one
+/// step ahead, the supervisor of `verify::StateGraph::supervise` also
+/// blocks controlled transitions into states from which uncontrolled
+/// ones reach a violation, which needs the whole state graph.
+fn allowed(ts: &TransitionSystemModel, t: &Transition, state: &SPState) -> bool {
+    if !t.eval(state) {
+        return false;
+    }
+    if t.type_ != TransitionType::Controlled {
+        return true;
+    }
+    let mut next = state.clone();
+    fire(t, &mut next).is_ok() && ts.specs.iter().all(|s| s.invariant().eval(&next))
+}
+
+/// Replay `trace` (indices into `transitions`) from `initial`.
+/// Returns the violated property, if any, and None if some step is
+/// not allowed.
+fn replay(ts: &TransitionSystemModel, transitions: &[&Transition],
+          initial: &SPState, trace: &[usize]) -> Option<String> {
+    let mut state = initial.clone();
+    for i in trace {
+        let t = transitions[*i];
+        if !allowed(ts, t, &state) || fire(t, &mut state).is_err() {
+            return None;
+        }
+        if let Some(p) = violated(ts, &state) {
+            return Some(p);
+        }
+    }
+    None
+}
+
+/// Drop steps from a failing trace as long as the same property is
+/// still violated.
+fn shrink(ts: &TransitionSystemModel, transitions: &[&Transition],
+          initial: &SPState, trace: Vec<usize>, property: &str) -> Vec<usize> {
+    let mut trace = trace;
+    let mut i = 0;
+    while i < trace.len() {
+        let mut shorter = trace.clone();
+        shorter.remove(i);
+        if replay(ts, transitions, initial, &shorter).as_deref() == Some(property) {
+            trace = shorter;
+        } else {
+            i += 1;
+        }
+    }
+    trace
+}
+
+/// Take `walks` random walks of at most `steps` supervised transitions
+/// from `initial`, checking all invariants and variable domains after
+/// each step. Controlled transitions are guarded one step ahead, see
+/// `allowed`. A violation means an effect or auto transition broke an
+/// invariant, either right away or after controlled transitions that
+/// a full supervisor could have blocked earlier, rerun `verify::verify`
+/// on a smaller model to tell which. Complements it when the state
+/// space is too large to explore. The walks are reproducible from
+/// `seed`.
+pub fn fuzz(ts: &TransitionSystemModel, initial: &SPState, seed: u64,
+            walks: usize, steps: usize) -> Result<(), FuzzFailure> {
+    let transitions = formal_transitions(ts);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    for _ in 0..walks {
+        let mut state = initial.clone();
+        let mut trace = vec![];
+        for _ in 0..steps {
+            let enabled: Vec<usize> = (0..transitions.len())
+                .filter(|i| allowed(ts, transitions[*i], &state))
+                .collect();
+            let i = match enabled.choose(&mut rng) {
+                Some(i) => *i,
+                None => break,
+            };
+            if fire(transitions[i], &mut state).is_err() {
+                break;
+            }
+            trace.push(i);
+            if let Some(property) = violated(ts, &state) {
+                let trace = shrink(ts, &transitions, initial, trace, &property);
+                return Err(FuzzFailure {
+                    seed,
+                    property,
+                    trace: trace.iter().map(|i| transitions[*i].path().to_string()).collect(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use crate::record::{explain, Entry};
+use crate::resources::interface::RosInterface;
+use crate::sim::{run_simulation, SimConfig};
+use futures::StreamExt;
+use sp_domain::*;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Why a run did not reach its goal, with the transitions fired so far.
+#[derive(Debug)]
+pub struct HarnessFailure {
+    pub reason: String,
+    pub trace: Vec<String>,
+    pub state: SPState,
+}
+
+impl fmt::Display for HarnessFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} after {} transition(s):", self.reason, self.trace.len())?;
+        for (i, t) in self.trace.iter().enumerate() {
+            writeln!(f, "  {:3}: {}", i, t)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn fire(t: &Transition, state: &mut SPState) -> SPResult<()> {
+    for a in t.actions() {
+        a.next(state)?;
+    }
+    state.take_transition();
+    Ok(())
+}
+
+/// Drive a transition system from `initial` until `goal` holds,
+/// without ROS. Resources are simulated by their formal effects:
+/// at each step we plan from the current state, fire the first
+/// transition of the plan and then any enabled auto transitions,
+/// like the runner would.
+///
+/// Fails if no plan exists, if an auto transition stays enabled
+/// without changing the state, or if the goal is not reached within
+/// `max_steps` fired transitions or `time_bound` wall clock time.
+pub fn run_to_goal(ts: &TransitionSystemModel, initial: &SPState, goal: &Predicate,
+                   max_steps: usize, time_bound: Duration) -> Result<Vec<String>, HarnessFailure> {
+    let started = Instant::now();
+    let mut state = initial.clone();
+    let mut trace: Vec<String> = Vec::new();
+
+    let fail = |reason: String, trace: Vec<String>, state: SPState| HarnessFailure { reason, trace, state };
+
+    loop {
+        if goal.eval(&state) {
+            return Ok(trace);
+        }
+        if trace.len() >= max_steps {
+            return Err(fail(format!("goal not reached in {} steps", max_steps), trace, state));
+        }
+        if started.elapsed() > time_bound {
+            return Err(fail(format!("goal not reached in {:?}", time_bound), trace, state));
+        }
+
+        let plan = sp_formal::planning::plan(ts, &[(goal.clone(), None)], &state, 50);
+        let first = match plan {
+            Ok(p) if p.plan_found => p.trace.first().map(|f| f.transition.clone()),
+            Ok(_) => return Err(fail("no plan to the goal".into(), trace, state)),
+            Err(e) => return Err(fail(format!("planner failed: {}", e), trace, state)),
+        };
+        let first = match first {
+            Some(f) => f,
+            None => return Err(fail("empty plan but goal not reached".into(), trace, state)),
+        };
+
+        let t = ts.transitions.iter().find(|t| t.path() == &first)
+            .unwrap_or_else(|| panic!("planned transition {} is not in the model", first));
+        if let Err(e) = fire(t, &mut state) {
+            return Err(fail(format!("could not fire {}: {}", first, e), trace, state));
+        }
+        trace.push(first.to_string());
+
+        // auto transitions are taken as soon as they are enabled.
+        while let Some(t) = ts.transitions.iter()
+            .find(|t| t.type_ == TransitionType::Auto && t.eval(&state))
+        {
+            if trace.len() >= max_steps {
+                break;
+            }
+            let before = state.clone();
+            if let Err(e) = fire(t, &mut state) {
+                return Err(fail(format!("could not fire {}: {}", t.path(), e), trace, state));
+            }
+            trace.push(t.path().to_string());
+            // still enabled in the same state, it would fire forever.
+            if t.actions().iter().all(|a| before.sp_value_from_path(&a.var) == state.sp_value_from_path(&a.var)) {
+                return Err(fail(format!("auto transition {} does not change the state", t.path()), trace, state));
+            }
+        }
+    }
+}
+
+/// Run an intention of `m` on the operation level, with the formal
+/// effects standing in for the resources. See `run_intention_simulated`
+/// for the runner and the simulated resources.
+pub fn run_intention(m: &Model, initial: &SPState, intention: &str,
+                     max_steps: usize, time_bound: Duration) -> Result<Vec<String>, HarnessFailure> {
+    let i = m.intentions.iter().find(|i| i.path().leaf() == intention)
+        .unwrap_or_else(|| panic!("no intention named {}", intention));
+    let ts = TransitionSystemModel::from_op(m);
+    run_to_goal(&ts, initial, i.goal(), max_steps, time_bound)
+}
+
+/// Run an intention end to end: the model on the runner started by
+/// `launch_model`, the resources simulated by `crate::sim`. The
+/// intention is started through /sp/set_state once the runner is up,
+/// and we follow /sp/state until it has finished.
+///
+/// Needs a ROS 2 environment. Fails if the intention has not finished
+/// within `time_bound`.
+pub async fn run_intention_simulated(m: Model, initial: SPState, intention: &str,
+                                     interface: RosInterface, sim: SimConfig,
+                                     time_bound: Duration) -> Result<Vec<String>, HarnessFailure> {
+    let i = m.intentions.iter().find(|i| i.path().leaf() == intention)
+        .unwrap_or_else(|| panic!("no intention named {}", intention));
+    let (path, goal) = (i.path().clone(), i.goal().clone());
+    let ts = TransitionSystemModel::from(&m);
+
+    let fail = |reason: String, trace: Vec<String>, state: SPState| HarnessFailure { reason, trace, state };
+    let ros = |e: SPError| fail(format!("ros: {}", e), vec![], SPState::new());
+
+    let ctx = r2r::Context::create().map_err(SPError::from_any).map_err(ros)?;
+    let mut node = r2r::Node::create(ctx, "sp_harness", "").map_err(SPError::from_any).map_err(ros)?;
+    let mut states = node
+        .subscribe::<r2r::std_msgs::msg::String>("/sp/state", r2r::QosProfile::default())
+        .map_err(SPError::from_any).map_err(ros)?;
+    let client = node
+        .create_client::<r2r::sp_msgs::srv::Json::Service>("/sp/set_state")
+        .map_err(SPError::from_any).map_err(ros)?;
+    let runner_up = node.is_available(&client).map_err(SPError::from_any).map_err(ros)?;
+    tokio::task::spawn_blocking(move || loop {
+        node.spin_once(Duration::from_millis(10));
+    });
+
+    tokio::spawn(async move { run_simulation(&interface, sim).await });
+    tokio::spawn(async move { sp_runner::launch_model(m, initial).await });
+
+    let started = Instant::now();
+    let mut trace: Vec<String> = Vec::new();
+    let mut state = SPState::new();
+    let mut previous = serde_json::Map::new();
+
+    if tokio::time::timeout(time_bound, runner_up).await.is_err() {
+        return Err(fail("the runner did not start".into(), trace, state));
+    }
+    let mut start = serde_json::Map::new();
+    start.insert(path.to_string(), "e".into());
+    let json = serde_json::Value::Object(start).to_string();
+    let started_intention = client
+        .request(&r2r::sp_msgs::srv::Json::Request { json })
+        .map_err(SPError::from_any).map_err(ros)?;
+    if tokio::time::timeout(time_bound, started_intention).await.is_err() {
+        return Err(fail(format!("could not start {}", intention), trace, state));
+    }
+
+    loop {
+        let left = match time_bound.checked_sub(started.elapsed()) {
+            Some(left) => left,
+            None => return Err(fail(format!("{} not finished in {:?}", intention, time_bound), trace, state)),
+        };
+        let msg = match tokio::time::timeout(left, states.next()).await {
+            Ok(Some(msg)) => msg,
+            Ok(None) => return Err(fail("the runner stopped publishing".into(), trace, state)),
+            Err(_) => continue,
+        };
+        let values = match serde_json::from_str::<serde_json::Value>(&msg.data) {
+            Ok(serde_json::Value::Object(values)) if values != previous => values,
+            _ => continue,
+        };
+        let next = Entry { time_ms: 0, state: values.clone(), fired: vec![] }.sp_state(&ts);
+        trace.extend(explain(&ts, &state, &next));
+        state = next;
+        previous = values;
+
+        let finished = state.sp_value_from_path(&path) == Some(&"f".to_spvalue());
+        if finished || goal.eval(&state) {
+            return Ok(trace);
+        }
+    }
+}
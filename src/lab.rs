@@ -0,0 +1,54 @@
+//! The PLC program of the lab cell, shared by scenario_1 and the
+//! simulation of it.
+
+use crate::resources::plc::{tag_key, Handshake, OpcNodeIds, PlcTag};
+use crate::sim::{frame_locker::SimFrameLocker, plc::SimPlc, SimConfig};
+use std::time::Duration;
+
+/// The sensor at the end of the conveyor.
+pub const PART_PRESENT: &str = "part_present";
+
+pub fn plc_tags() -> Vec<PlcTag> {
+    vec![
+        PlcTag::bool_to_plc("load_start", "bool_to_plc_1"),
+        PlcTag::bool_from_plc("load_done", "bool_from_plc_1"),
+        PlcTag::bool_to_plc("unload_start", "bool_to_plc_2"),
+        PlcTag::bool_from_plc("unload_done", "bool_from_plc_2"),
+        PlcTag::bool_from_plc(PART_PRESENT, "bool_from_plc_3"),
+    ]
+}
+
+/// Loading and unloading the conveyor, they take a few seconds in
+/// the PLC program.
+pub fn conveyor_handshakes() -> [Handshake; 2] {
+    [
+        Handshake::new("load", "load_start", "load_done")
+            .with_timeout(Duration::from_secs(20)),
+        Handshake::new("unload", "unload_start", "unload_done")
+            .with_timeout(Duration::from_secs(20)),
+    ]
+}
+
+/// Simulated hardware for the cell. The PLC answers the handshakes,
+/// and the part shows up at the sensor when loading and leaves it
+/// when unloading.
+pub fn sim_config() -> SimConfig {
+    let tags = plc_tags();
+    let node_ids = OpcNodeIds::default();
+    let [load, unload] = conveyor_handshakes();
+
+    let mut plc = SimPlc::answering(&tags, &node_ids, &[load.clone(), unload.clone()]);
+    let part_present = tag_key(&tags, &node_ids, PART_PRESENT);
+    plc.latch = vec![
+        (tag_key(&tags, &node_ids, &load.request), part_present.clone(), true.into()),
+        (tag_key(&tags, &node_ids, &unload.request), part_present, false.into()),
+    ];
+
+    SimConfig {
+        plc,
+        frame_locker: SimFrameLocker {
+            frame_ids: vec!["aruco".into()],
+        },
+        ..Default::default()
+    }
+}
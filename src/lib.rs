@@ -1,9 +1,36 @@
 pub mod resources;
+pub mod checks;
+pub mod harness;
+pub mod plan_check;
+pub mod verify;
+pub mod fuzz;
+pub mod dot;
+pub mod report;
+pub mod diff;
+pub mod record;
+pub mod parts;
+pub mod storage;
+pub mod watchdog;
+pub mod modbus;
+pub mod sim;
+pub mod lab;
 
+use diff::{diff_models, ModelDiff};
 use sp_domain::*;
 
-/// send model + state to sp-launch
-pub async fn update_sp(compiled_model: &sp_formal::CompiledModel, state: &SPState) {
+/// send model + state to sp-launch, replacing `previous`. the changes
+/// are printed first and nothing is sent unless `accept` lets them
+/// through, e.g. |d| d.in_category("invariant").next().is_none()
+/// to never push a model with changed invariants.
+pub async fn update_sp(previous: &Model, model: &Model,
+                       compiled_model: &sp_formal::CompiledModel, state: &SPState,
+                       accept: impl Fn(&ModelDiff) -> bool) -> Result<(), ModelDiff> {
+    let changes = diff_models(previous, model);
+    println!("{}", changes);
+    if !accept(&changes) {
+        return Err(changes);
+    }
+
     let state_json = SPStateJson::from_state_flat(&state)
         .to_json()
         .to_string();
@@ -48,6 +75,7 @@ pub async fn update_sp(compiled_model: &sp_formal::CompiledModel, state: &SPStat
 
     *kill.lock().unwrap() = true;
     spin_handle.await.unwrap();
+    Ok(())
 }
 
 /// fix for indention problem...
@@ -0,0 +1,294 @@
+use crate::resources::modbus::{ModbusKeys, ModbusRegister};
+use crate::resources::plc::{PlcDirection, PlcTag, TagKeys};
+use futures::StreamExt;
+use sp_domain::*;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+pub(crate) const READ_COILS: u8 = 0x01;
+pub(crate) const READ_HOLDING_REGISTERS: u8 = 0x03;
+pub(crate) const WRITE_SINGLE_COIL: u8 = 0x05;
+pub(crate) const WRITE_SINGLE_REGISTER: u8 = 0x06;
+
+/// Minimal Modbus TCP client, only what the bridge needs.
+pub struct ModbusClient {
+    stream: TcpStream,
+    unit_id: u8,
+    transaction: u16,
+}
+
+pub(crate) fn encode_frame(transaction: u16, unit_id: u8, pdu: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(7 + pdu.len());
+    frame.extend_from_slice(&transaction.to_be_bytes());
+    frame.extend_from_slice(&0u16.to_be_bytes()); // protocol id
+    frame.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+    frame.push(unit_id);
+    frame.extend_from_slice(pdu);
+    frame
+}
+
+fn decode_bits(bytes: &[u8], count: usize) -> Vec<bool> {
+    (0..count).map(|i| bytes[i / 8] & (1 << (i % 8)) != 0).collect()
+}
+
+pub(crate) fn encode_bits(bits: &[bool]) -> Vec<u8> {
+    let mut bytes = vec![0u8; (bits.len() + 7) / 8];
+    for (i, _) in bits.iter().enumerate().filter(|(_, b)| **b) {
+        bytes[i / 8] |= 1 << (i % 8);
+    }
+    bytes
+}
+
+fn protocol_error(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl ModbusClient {
+    pub async fn connect(address: &str, unit_id: u8) -> io::Result<ModbusClient> {
+        let stream = TcpStream::connect(address).await?;
+        Ok(ModbusClient { stream, unit_id, transaction: 0 })
+    }
+
+    /// Send a request pdu and return the response pdu.
+    async fn request(&mut self, pdu: &[u8]) -> io::Result<Vec<u8>> {
+        self.transaction = self.transaction.wrapping_add(1);
+        self.stream.write_all(&encode_frame(self.transaction, self.unit_id, pdu)).await?;
+
+        let mut header = [0u8; 7];
+        self.stream.read_exact(&mut header).await?;
+        let transaction = u16::from_be_bytes([header[0], header[1]]);
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        if transaction != self.transaction || length < 2 {
+            return Err(protocol_error(format!("unexpected modbus header {:?}", header)));
+        }
+        let mut response = vec![0u8; length - 1];
+        self.stream.read_exact(&mut response).await?;
+
+        if response[0] == pdu[0] | 0x80 {
+            return Err(protocol_error(format!(
+                "modbus exception {} for function {}", response.get(1).unwrap_or(&0), pdu[0])));
+        }
+        if response[0] != pdu[0] {
+            return Err(protocol_error(format!("unexpected modbus function {}", response[0])));
+        }
+        Ok(response)
+    }
+
+    pub async fn read_coils(&mut self, address: u16, count: u16) -> io::Result<Vec<bool>> {
+        let mut pdu = vec![READ_COILS];
+        pdu.extend_from_slice(&address.to_be_bytes());
+        pdu.extend_from_slice(&count.to_be_bytes());
+        let response = self.request(&pdu).await?;
+        let bytes = response.get(2..).unwrap_or(&[]);
+        if bytes.len() * 8 < count as usize {
+            return Err(protocol_error("short coil response".into()));
+        }
+        Ok(decode_bits(bytes, count as usize))
+    }
+
+    pub async fn read_holding_registers(&mut self, address: u16, count: u16) -> io::Result<Vec<u16>> {
+        let mut pdu = vec![READ_HOLDING_REGISTERS];
+        pdu.extend_from_slice(&address.to_be_bytes());
+        pdu.extend_from_slice(&count.to_be_bytes());
+        let response = self.request(&pdu).await?;
+        let bytes = response.get(2..).unwrap_or(&[]);
+        if bytes.len() < 2 * count as usize {
+            return Err(protocol_error("short register response".into()));
+        }
+        Ok(bytes.chunks(2).take(count as usize).map(|b| u16::from_be_bytes([b[0], b[1]])).collect())
+    }
+
+    pub async fn write_coil(&mut self, address: u16, value: bool) -> io::Result<()> {
+        let mut pdu = vec![WRITE_SINGLE_COIL];
+        pdu.extend_from_slice(&address.to_be_bytes());
+        pdu.extend_from_slice(&(if value { 0xFF00u16 } else { 0x0000 }).to_be_bytes());
+        self.request(&pdu).await.map(|_| ())
+    }
+
+    pub async fn write_register(&mut self, address: u16, value: u16) -> io::Result<()> {
+        let mut pdu = vec![WRITE_SINGLE_REGISTER];
+        pdu.extend_from_slice(&address.to_be_bytes());
+        pdu.extend_from_slice(&value.to_be_bytes());
+        self.request(&pdu).await.map(|_| ())
+    }
+}
+
+/// Settings for serving a PLCResource with resources::modbus::ModbusKeys
+/// from a Modbus TCP server. Runs in the same process as the runner
+/// and replaces the OPC UA bridge only: it speaks the same JSON on
+/// the same command and measured topics, the PLC resource itself
+/// does not know about Modbus.
+pub struct ModbusBridge {
+    pub address: String, // e.g. "192.168.1.10:502"
+    pub unit_id: u8,
+    pub command_topic: String, // e.g. "/opc_command"
+    pub measured_topic: String, // e.g. "/opc_measured"
+    pub tags: Vec<PlcTag>,
+    pub poll_period: Duration,
+    /// Wait this long before the first reconnect, doubling up to
+    /// `max_backoff` while the server stays away.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+type Registers<'a> = [(&'a PlcTag, String, ModbusRegister)];
+
+/// Write what changed in the command since the last write, then
+/// read all measured registers.
+async fn poll(client: &mut ModbusClient, registers: &Registers<'_>, command: Option<&serde_json::Value>,
+              written: &mut HashMap<String, serde_json::Value>) -> io::Result<serde_json::Map<String, serde_json::Value>> {
+    if let Some(command) = command {
+        for (_, key, register) in registers.iter().filter(|(t, _, _)| t.direction == PlcDirection::ToPlc) {
+            let value = match command.get(key) {
+                Some(v) if written.get(key) != Some(v) => v.clone(),
+                _ => continue,
+            };
+            match *register {
+                ModbusRegister::Coil(address) =>
+                    client.write_coil(address, value.as_bool().unwrap_or(false)).await?,
+                ModbusRegister::HoldingRegister(address) =>
+                    client.write_register(address, value.as_i64().unwrap_or(0) as i16 as u16).await?,
+            }
+            written.insert(key.clone(), value);
+        }
+    }
+
+    let mut measured = serde_json::Map::new();
+    for (_, key, register) in registers.iter().filter(|(t, _, _)| t.direction == PlcDirection::FromPlc) {
+        let value = match *register {
+            ModbusRegister::Coil(address) => serde_json::json!(client.read_coils(address, 1).await?[0]),
+            ModbusRegister::HoldingRegister(address) =>
+                serde_json::json!(client.read_holding_registers(address, 1).await?[0] as i16),
+        };
+        measured.insert(key.clone(), value);
+    }
+    Ok(measured)
+}
+
+/// Forward commands to the server and publish polled values. When
+/// the connection fails nothing is published, so the PLC watchdog
+/// sees the link go down, and we reconnect with backoff. The latest
+/// command is written again in full after a reconnect. Runs until
+/// the node fails.
+pub async fn run_modbus_bridge(config: ModbusBridge) -> SPResult<()> {
+    let registers: Vec<(&PlcTag, String, ModbusRegister)> = config.tags.iter()
+        .map(|t| (t, ModbusKeys.key(t), ModbusRegister::of(t)))
+        .collect();
+
+    let ctx = r2r::Context::create().map_err(SPError::from_any)?;
+    let mut node = r2r::Node::create(ctx, "modbus_bridge", "").map_err(SPError::from_any)?;
+
+    let mut commands = node
+        .subscribe::<r2r::std_msgs::msg::String>(&config.command_topic, r2r::QosProfile::default())
+        .map_err(SPError::from_any)?;
+    let publisher = node
+        .create_publisher::<r2r::std_msgs::msg::String>(&config.measured_topic, r2r::QosProfile::default())
+        .map_err(SPError::from_any)?;
+
+    // latest command, written to the server on the next poll.
+    let latest: Arc<Mutex<Option<serde_json::Value>>> = Arc::new(Mutex::new(None));
+    let l = latest.clone();
+    tokio::spawn(async move {
+        while let Some(msg) = commands.next().await {
+            if let Ok(json) = serde_json::from_str(&msg.data) {
+                *l.lock().unwrap() = Some(json);
+            }
+        }
+    });
+
+    let mut command = None;
+    let mut backoff = config.backoff;
+    loop {
+        let mut client = match ModbusClient::connect(&config.address, config.unit_id).await {
+            Ok(client) => client,
+            Err(e) => {
+                println!("modbus: cannot connect to {}: {}, retrying in {:?}", config.address, e, backoff);
+                node.spin_once(Duration::from_millis(10));
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(config.max_backoff);
+                continue;
+            }
+        };
+        backoff = config.backoff;
+
+        // the server may have restarted, write everything again.
+        let mut written: HashMap<String, serde_json::Value> = HashMap::new();
+        let mut interval = tokio::time::interval(config.poll_period);
+        loop {
+            interval.tick().await;
+            node.spin_once(Duration::from_millis(10));
+
+            if let Some(c) = latest.lock().unwrap().take() {
+                command = Some(c);
+            }
+            let measured = match poll(&mut client, &registers, command.as_ref(), &mut written).await {
+                Ok(measured) => measured,
+                Err(e) => {
+                    println!("modbus: lost connection to {}: {}", config.address, e);
+                    break;
+                }
+            };
+            let data = serde_json::Value::Object(measured).to_string();
+            publisher
+                .publish(&r2r::std_msgs::msg::String { data })
+                .map_err(SPError::from_any)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn frame_layout() {
+        let frame = encode_frame(7, 1, &[READ_COILS, 0, 3, 0, 2]);
+        assert_eq!(frame, vec![0, 7, 0, 0, 0, 6, 1, READ_COILS, 0, 3, 0, 2]);
+    }
+
+    #[test]
+    fn tags_map_to_registers() {
+        let start = PlcTag::bool_to_plc("start", 3u32);
+        let count = PlcTag::int_from_plc("count", 40u32, vec![]);
+        assert_eq!(ModbusRegister::of(&start), ModbusRegister::Coil(3));
+        assert_eq!(ModbusRegister::of(&count), ModbusRegister::HoldingRegister(40));
+        assert_eq!(ModbusKeys.key(&start), "coil/3");
+        assert_eq!(ModbusKeys.key(&count), "hr/40");
+    }
+
+    #[test]
+    fn bits_are_lsb_first() {
+        assert_eq!(decode_bits(&[0b0000_0101], 3), vec![true, false, true]);
+        assert_eq!(encode_bits(&[true, false, true]), vec![0b0000_0101]);
+    }
+
+    // a tiny modbus server that answers read holding registers
+    // with the register address as value.
+    #[tokio::test]
+    async fn read_registers_from_local_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 12];
+            socket.read_exact(&mut request).await.unwrap();
+            let start = u16::from_be_bytes([request[8], request[9]]);
+            let count = u16::from_be_bytes([request[10], request[11]]);
+            let mut pdu = vec![READ_HOLDING_REGISTERS, (count * 2) as u8];
+            for r in start..start + count {
+                pdu.extend_from_slice(&r.to_be_bytes());
+            }
+            let transaction = u16::from_be_bytes([request[0], request[1]]);
+            socket.write_all(&encode_frame(transaction, request[6], &pdu)).await.unwrap();
+        });
+
+        let mut client = ModbusClient::connect(&address, 1).await.unwrap();
+        let registers = client.read_holding_registers(10, 3).await.unwrap();
+        assert_eq!(registers, vec![10, 11, 12]);
+    }
+}
@@ -0,0 +1,160 @@
+use sp_domain::*;
+
+/// Location of parts that are not in the cell.
+pub const OUTSIDE: &str = "none";
+
+pub struct Part {
+    pub name: String,
+    pub part_type: String,
+    pub location: SPPath, // product state, domain is the tracker locations
+}
+
+/// Tracks where each part is instead of keeping one boolean per
+/// location. Every part gets a product variable over the same domain
+/// of locations, and a location holds at most one part. Bins, like
+/// outside, hold any number of parts.
+pub struct PartTracker {
+    pub locations: Vec<String>,
+    pub bins: Vec<String>,
+    pub parts: Vec<Part>,
+}
+
+impl PartTracker {
+    pub fn new(locations: &[&str]) -> PartTracker {
+        let mut all = vec![OUTSIDE.to_string()];
+        all.extend(locations.iter().map(|l| l.to_string()));
+        PartTracker {
+            locations: all,
+            bins: vec![],
+            parts: vec![],
+        }
+    }
+
+    /// Let `location` hold any number of parts, e.g. a drop-out box.
+    /// Parts in a bin have left the cell.
+    pub fn add_bin(&mut self, location: &str) {
+        self.check_location(location);
+        self.bins.push(location.into());
+    }
+
+    fn is_bin(&self, location: &str) -> bool {
+        location == OUTSIDE || self.bins.iter().any(|b| b == location)
+    }
+
+    /// Add a part, initially outside of the cell.
+    pub fn add_part(&mut self, model: &mut Model, name: &str, part_type: &str) -> SPPath {
+        let domain: Vec<SPValue> = self.locations.iter().map(|l| l.to_spvalue()).collect();
+        let location = model.add_product_domain(&format!("{}_location", name), &domain);
+        self.parts.push(Part {
+            name: name.into(),
+            part_type: part_type.into(),
+            location: location.clone(),
+        });
+        location
+    }
+
+    pub fn part(&self, name: &str) -> &Part {
+        self.parts.iter().find(|p| p.name == name)
+            .unwrap_or_else(|| panic!("no part named {}", name))
+    }
+
+    pub fn parts_of_type<'a>(&'a self, part_type: &'a str) -> impl Iterator<Item = &'a Part> + 'a {
+        self.parts.iter().filter(move |p| p.part_type == part_type)
+    }
+
+    fn check_location(&self, location: &str) {
+        if !self.locations.iter().any(|l| l == location) {
+            panic!("unknown part location {}", location);
+        }
+    }
+
+    pub fn at(&self, part: &str, location: &str) -> Predicate {
+        self.check_location(location);
+        let loc = &self.part(part).location;
+        p!(loc == location)
+    }
+
+    /// True when no part is at `location`. Outside and the bins are
+    /// always free.
+    pub fn is_free(&self, location: &str) -> Predicate {
+        self.check_location(location);
+        if self.is_bin(location) {
+            return Predicate::TRUE;
+        }
+        Predicate::AND(self.parts.iter().map(|p| {
+            let loc = &p.location;
+            p!(loc != location)
+        }).collect())
+    }
+
+    pub fn is_occupied(&self, location: &str) -> Predicate {
+        Predicate::NOT(Box::new(self.is_free(location)))
+    }
+
+    /// True when all parts have left the cell, outside or to a bin.
+    pub fn all_outside(&self) -> Predicate {
+        Predicate::AND(self.parts.iter().map(|p| {
+            let loc = &p.location;
+            Predicate::OR(self.locations.iter().filter(|l| self.is_bin(l)).map(|l| {
+                p!(loc == l)
+            }).collect())
+        }).collect())
+    }
+
+    /// Operations for the first part of a type keep `name`, the other
+    /// parts get their name appended. A model with one part of each
+    /// type keeps its operation names when parts are added.
+    pub fn op_name(&self, name: &str, part: &str) -> String {
+        let part_type = &self.part(part).part_type;
+        match self.parts_of_type(part_type).next() {
+            Some(first) if first.name == part => name.to_string(),
+            _ => format!("{}_{}", name, part),
+        }
+    }
+
+    /// Operation moving `part` from one location to another, e.g. a
+    /// pick (to the gripper) or a place (from the gripper). The
+    /// target must be free. Goal and actions are the low level ones.
+    /// The operation is named by `op_name`.
+    pub fn add_move_op(&self, model: &mut Model, name: &str, part: &str,
+                       from: &str, to: &str, guard: &Predicate,
+                       goal: &Predicate, actions: &[Action]) {
+        self.check_location(to);
+        let loc = &self.part(part).location;
+        let at_from = self.at(part, from);
+        let to_free = self.is_free(to);
+        let guard = guard.clone();
+        model.add_op(
+            &self.op_name(name, part),
+            &p!([p: at_from] && [p: to_free] && [p: guard]),
+            &[a!(loc <- to)],
+            goal,
+            actions,
+            false,
+            None,
+        );
+    }
+
+    /// A location in the cell holds at most one part.
+    pub fn add_invariants(&self, model: &mut Model) {
+        for (i, a) in self.parts.iter().enumerate() {
+            for b in &self.parts[i + 1..] {
+                let la = &a.location;
+                let lb = &b.location;
+                for l in self.locations.iter().filter(|l| !self.is_bin(l)) {
+                    model.add_invar(
+                        &format!("{}_and_{}_not_both_at_{}", a.name, b.name, l),
+                        &Predicate::NOT(Box::new(p!([la == l] && [lb == l]))),
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn initial_state(&self) -> SPState {
+        let values: Vec<_> = self.parts.iter()
+            .map(|p| (p.location.clone(), OUTSIDE.to_spvalue()))
+            .collect();
+        SPState::new_from_values(&values)
+    }
+}
@@ -0,0 +1,104 @@
+use crate::verify::{explore, formal_transitions, operation_transitions};
+use sp_domain::*;
+use std::fmt;
+
+/// Result of planning towards one named goal.
+#[derive(Debug)]
+pub struct PlanCheck {
+    pub name: String,
+    pub length: Option<usize>, // None when no plan was found
+}
+
+/// Plan lengths for a set of goals, printable as a report.
+#[derive(Debug)]
+pub struct PlanReport {
+    pub checks: Vec<PlanCheck>,
+    pub complete: bool, // false if a search hit its limit, missing plans may exist
+}
+
+impl PlanReport {
+    pub fn unreachable(&self) -> Vec<&str> {
+        self.checks.iter().filter(|c| c.length.is_none()).map(|c| c.name.as_str()).collect()
+    }
+}
+
+impl fmt::Display for PlanReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.complete {
+            writeln!(f, "  (search limit reached, not complete)")?;
+        }
+        for c in &self.checks {
+            match c.length {
+                Some(l) => writeln!(f, "  {:40} plan length {}", c.name, l)?,
+                None => writeln!(f, "  {:40} NO PLAN", c.name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Preconditions of all operations, taken from the operation level
+/// transition system.
+pub fn operation_goals(ts: &TransitionSystemModel) -> Vec<(String, Predicate)> {
+    ts.transitions
+        .iter()
+        .map(|t| (t.path().leaf(), t.guard().clone()))
+        .collect()
+}
+
+/// Goals of all intentions that have one (GUI driven intentions have
+/// a FALSE goal and are skipped).
+pub fn intention_goals(m: &Model) -> Vec<(String, Predicate)> {
+    m.intentions
+        .iter()
+        .filter(|i| i.goal() != &Predicate::FALSE)
+        .map(|i| (i.path().leaf(), i.goal().clone()))
+        .collect()
+}
+
+/// Try to plan from `state` to each goal. The planner only looks
+/// `max_steps` ahead, a goal without a plan may still be reachable
+/// and the report is then not complete.
+pub fn check_plans(ts: &TransitionSystemModel, state: &SPState,
+                   goals: &[(String, Predicate)], max_steps: u32) -> PlanReport {
+    let checks: Vec<PlanCheck> = goals
+        .iter()
+        .map(|(name, goal)| {
+            let plan = sp_formal::planning::plan(ts, &[(goal.clone(), None)], state, max_steps);
+            let length = match plan {
+                Ok(p) if p.plan_found => Some(p.trace.len()),
+                _ => None,
+            };
+            PlanCheck { name: name.clone(), length }
+        })
+        .collect();
+    let complete = checks.iter().all(|c| c.length.is_some());
+    PlanReport { checks, complete }
+}
+
+/// Check that each operation can also finish from `state`: some state
+/// is reachable where it executes and its goal holds, so that it
+/// finishes with its effects. Searched in the full model, with the
+/// operations as the runner executes them. The length counts the low
+/// level transitions up to and including the finish.
+pub fn check_completions(m: &Model, state: &SPState, max_states: usize) -> PlanReport {
+    let ts = TransitionSystemModel::from(m);
+    let ops = operation_transitions(m);
+    let mut transitions = formal_transitions(&ts);
+    transitions.extend(ops.iter());
+    let graph = explore(&transitions, state, max_states);
+
+    let checks = m.operations
+        .iter()
+        .map(|o| {
+            let op = o.path();
+            let goal = o.goal().clone();
+            let finishing = p!([op == "e"] && [p: goal]);
+            let length = graph.states.iter()
+                .position(|s| finishing.eval(s))
+                .map(|i| graph.trace(i).len() + 1);
+            PlanCheck { name: o.path().leaf(), length }
+        })
+        .collect();
+    PlanReport { checks, complete: graph.complete }
+}
@@ -0,0 +1,237 @@
+use crate::harness::fire;
+use crate::resources::interface::RosInterface;
+use crate::sim::{run_simulation, SimConfig};
+use crate::verify::formal_transitions;
+use futures::StreamExt;
+use sp_domain::*;
+use std::io::{BufRead, Write};
+use std::time::{Duration, Instant};
+
+/// One recorded runner state.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub time_ms: u64, // since the recording started
+    pub state: serde_json::Map<String, serde_json::Value>, // flat, path -> value
+    pub fired: Vec<String>, // transitions the runner took since the previous entry
+}
+
+impl Entry {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "time_ms": self.time_ms,
+            "state": self.state,
+            "fired": self.fired,
+        })
+    }
+
+    fn from_json(json: &serde_json::Value) -> Option<Entry> {
+        Some(Entry {
+            time_ms: json.get("time_ms")?.as_u64()?,
+            state: json.get("state")?.as_object()?.clone(),
+            fired: json.get("fired")?.as_array()?.iter()
+                .filter_map(|f| f.as_str().map(|s| s.to_string()))
+                .collect(),
+        })
+    }
+
+    /// The recorded values of the variables of `ts`.
+    pub fn sp_state(&self, ts: &TransitionSystemModel) -> SPState {
+        let values: Vec<_> = ts.vars.iter().filter_map(|v| {
+            let json = self.state.get(&v.path().to_string())?;
+            Some((v.path().clone(), SPValue::from_json(json, v.value_type())))
+        }).collect();
+        SPState::new_from_values(&values)
+    }
+}
+
+/// A recording is stored as json lines, one entry per line, so that
+/// it can be followed with tail during a run.
+pub fn save(path: &std::path::Path, entries: &[Entry]) -> SPResult<()> {
+    let mut file = std::fs::File::create(path).map_err(SPError::from_any)?;
+    for e in entries {
+        writeln!(file, "{}", e.to_json()).map_err(SPError::from_any)?;
+    }
+    Ok(())
+}
+
+pub fn load(path: &std::path::Path) -> SPResult<Vec<Entry>> {
+    let file = std::fs::File::open(path).map_err(SPError::from_any)?;
+    let mut entries = vec![];
+    for (i, line) in std::io::BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(SPError::from_any)?;
+        let json: serde_json::Value = serde_json::from_str(&line).map_err(SPError::from_any)?;
+        match Entry::from_json(&json) {
+            Some(e) => entries.push(e),
+            None => return Err(SPError::from_any(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("bad entry on line {} of {:?}", i + 1, path)))),
+        }
+    }
+    Ok(entries)
+}
+
+/// Let the runner write the name of each transition it takes to a
+/// runner variable, which is published with the rest of the state.
+/// Effects are not taken by the runner, they show up as changed
+/// measurements. Returns the variable to pass to `run_recorder`.
+pub fn log_fired(m: &mut Model) -> SPPath {
+    let r = m.add_resource("recorder");
+    let fired = m.get_resource(&r).add_variable(Variable::new(
+        "fired", VariableType::Runner, SPValueType::String, vec![]));
+    for r in m.resources.iter_mut() {
+        for t in r.transitions.iter_mut().filter(|t| t.type_ != TransitionType::Effect) {
+            let name = t.path().to_string();
+            t.runner_actions.push(a!(fired <- name));
+        }
+    }
+    fired
+}
+
+/// The transition the runner logged in `state` with `log_fired`, if
+/// it is not the one logged in `previous`. The runner publishes its
+/// state after every step, a transition taken twice in a row is only
+/// seen once.
+fn fired(fired: &SPPath, previous: Option<&Entry>, state: &serde_json::Map<String, serde_json::Value>) -> Vec<String> {
+    let key = fired.to_string();
+    let now = state.get(&key);
+    match now {
+        Some(serde_json::Value::String(name)) if previous.and_then(|p| p.state.get(&key)) != now =>
+            vec![name.clone()],
+        _ => vec![],
+    }
+}
+
+/// Transitions of `ts` that were enabled in `before` and whose actions
+/// agree with `after`. For when the model does not log what the runner
+/// took, this is our best guess.
+pub fn explain(ts: &TransitionSystemModel, before: &SPState, after: &SPState) -> Vec<String> {
+    formal_transitions(ts)
+        .into_iter()
+        .filter(|t| t.eval(before))
+        .filter(|t| {
+            let mut next = before.clone();
+            fire(t, &mut next).is_ok() && !t.actions().is_empty() && t.actions().iter().all(|a| {
+                next.sp_value_from_path(&a.var) == after.sp_value_from_path(&a.var)
+                    && before.sp_value_from_path(&a.var) != after.sp_value_from_path(&a.var)
+            })
+        })
+        .map(|t| t.path().to_string())
+        .collect()
+}
+
+/// Record every state the runner publishes on `state_topic` (flat
+/// SPStateJson in a std_msgs/String) to `path`, with the transitions
+/// the runner took as logged in `fired_path` (see `log_fired`). Runs
+/// until the node fails.
+pub async fn run_recorder(fired_path: SPPath, state_topic: &str, path: &std::path::Path) -> SPResult<()> {
+    let ctx = r2r::Context::create().map_err(SPError::from_any)?;
+    let mut node = r2r::Node::create(ctx, "sp_recorder", "").map_err(SPError::from_any)?;
+    let mut states = node
+        .subscribe::<r2r::std_msgs::msg::String>(state_topic, r2r::QosProfile::default())
+        .map_err(SPError::from_any)?;
+    tokio::task::spawn_blocking(move || loop {
+        node.spin_once(Duration::from_millis(10));
+    });
+
+    let mut file = std::fs::File::create(path).map_err(SPError::from_any)?;
+    let started = Instant::now();
+    let mut previous: Option<Entry> = None;
+    while let Some(msg) = states.next().await {
+        let state = match serde_json::from_str::<serde_json::Value>(&msg.data) {
+            Ok(serde_json::Value::Object(state)) => state,
+            _ => continue,
+        };
+        if previous.as_ref().map(|p| p.state == state).unwrap_or(false) {
+            continue;
+        }
+        let entry = Entry {
+            time_ms: started.elapsed().as_millis() as u64,
+            fired: fired(&fired_path, previous.as_ref(), &state),
+            state,
+        };
+        writeln!(file, "{}", entry.to_json()).map_err(SPError::from_any)?;
+        previous = Some(entry);
+    }
+    Ok(())
+}
+
+/// Replay a recording offline: the model `m` (with `log_fired`) on a
+/// runner started by `launch_model`, its resources simulated by
+/// `crate::sim` on `interface`, which should not be the one of the
+/// lab. Once the runner is up, the recorded measured values are fed
+/// to it through /sp/set_state, keeping the recorded timing scaled by
+/// `speed`, and what the runner does in response is recorded.
+///
+/// Needs a ROS 2 environment. Returns the states of the replay, to
+/// compare with the recording.
+pub async fn run_replay(m: Model, initial: SPState, fired_path: SPPath, entries: &[Entry], speed: f64,
+                        interface: RosInterface, sim: SimConfig) -> SPResult<Vec<Entry>> {
+    let ts = TransitionSystemModel::from(&m);
+    let measured: Vec<String> = ts.vars.iter()
+        .filter(|v| v.variable_type() == VariableType::Measured)
+        .map(|v| v.path().to_string())
+        .collect();
+
+    let ctx = r2r::Context::create().map_err(SPError::from_any)?;
+    let mut node = r2r::Node::create(ctx, "sp_replay", "").map_err(SPError::from_any)?;
+    let mut states = node
+        .subscribe::<r2r::std_msgs::msg::String>("/sp/state", r2r::QosProfile::default())
+        .map_err(SPError::from_any)?;
+    let client = node
+        .create_client::<r2r::sp_msgs::srv::Json::Service>("/sp/set_state")
+        .map_err(SPError::from_any)?;
+    let runner_up = node.is_available(&client).map_err(SPError::from_any)?;
+    tokio::task::spawn_blocking(move || loop {
+        node.spin_once(Duration::from_millis(10));
+    });
+
+    tokio::spawn(async move { run_simulation(&interface, sim).await });
+    tokio::spawn(async move { sp_runner::launch_model(m, initial).await });
+    runner_up.await.map_err(SPError::from_any)?;
+
+    // record the runner while we feed it.
+    let recording = std::sync::Arc::new(std::sync::Mutex::new(Vec::<Entry>::new()));
+    let started = Instant::now();
+    let rec = recording.clone();
+    let recorder = tokio::spawn(async move {
+        while let Some(msg) = states.next().await {
+            let state = match serde_json::from_str::<serde_json::Value>(&msg.data) {
+                Ok(serde_json::Value::Object(state)) => state,
+                _ => continue,
+            };
+            let mut rec = rec.lock().unwrap();
+            if rec.last().map(|p| p.state == state).unwrap_or(false) {
+                continue;
+            }
+            let entry = Entry {
+                time_ms: started.elapsed().as_millis() as u64,
+                fired: fired(&fired_path, rec.last(), &state),
+                state,
+            };
+            println!("replay {:7}ms {}", entry.time_ms, entry.fired.join(", "));
+            rec.push(entry);
+        }
+    });
+
+    for (i, e) in entries.iter().enumerate() {
+        let at = Duration::from_millis((e.time_ms as f64 / speed) as u64);
+        if let Some(wait) = at.checked_sub(started.elapsed()) {
+            tokio::time::sleep(wait).await;
+        }
+        let values: serde_json::Map<_, _> = e.state.iter()
+            .filter(|(k, _)| measured.contains(k))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let json = serde_json::Value::Object(values).to_string();
+        client
+            .request(&r2r::sp_msgs::srv::Json::Request { json })
+            .map_err(SPError::from_any)?
+            .await
+            .map_err(SPError::from_any)?;
+        println!("{:5} {:7}ms {}", i, e.time_ms, e.fired.join(", "));
+    }
+
+    recorder.abort();
+    let replayed = recording.lock().unwrap().clone();
+    Ok(replayed)
+}
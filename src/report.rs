@@ -0,0 +1,165 @@
+use sp_domain::*;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+fn anchor(path: &SPPath) -> String {
+    path.to_string().replace('/', "-")
+}
+
+/// Operations and intentions are also variables, so the anchors of
+/// their sections are prefixed with what they are.
+fn section(kind: &str, path: &SPPath) -> String {
+    format!("{}-{}", kind, anchor(path))
+}
+
+fn link(path: &SPPath) -> String {
+    format!("[`{}`](#{})", path, anchor(path))
+}
+
+/// Something in the model that reads (guards, goals, invariants) or
+/// writes (actions) variables, linked to by its anchor.
+struct User<'a> {
+    name: String,
+    anchor: String,
+    reads: Vec<&'a Predicate>,
+    writes: &'a [Action],
+}
+
+/// Who uses each variable.
+fn usages(users: &[User]) -> HashMap<SPPath, Vec<String>> {
+    let mut used: HashMap<SPPath, Vec<String>> = HashMap::new();
+    for u in users {
+        let mut vars: Vec<SPPath> = u.reads.iter().flat_map(|p| p.support()).collect();
+        vars.extend(u.writes.iter().map(|a| a.var.clone()));
+        let entry = format!("[{}](#{})", u.name, u.anchor);
+        for v in vars {
+            let list = used.entry(v).or_default();
+            if !list.contains(&entry) {
+                list.push(entry.clone());
+            }
+        }
+    }
+    used
+}
+
+fn transition(out: &mut String, name: &str, anchor: &str, kind: &str, guard: &Predicate, actions: &[Action]) {
+    writeln!(out, "#### {} <a id=\"{}\"></a>\n", name, anchor).unwrap();
+    writeln!(out, "{}\n", kind).unwrap();
+    writeln!(out, "- guard: `{}`", guard).unwrap();
+    for a in actions {
+        writeln!(out, "- action: `{}`", a).unwrap();
+    }
+    out.push('\n');
+}
+
+/// A Markdown report of a model, for reviewers. Variables link to
+/// where they are used, and transitions, operations, invariants and
+/// intentions have anchors named by their full path.
+pub fn model_report(m: &Model, initial: &SPState) -> String {
+    let ts = TransitionSystemModel::from(m);
+    let ops = TransitionSystemModel::from_op(m);
+    let op_transition = |o: &Operation| ops.transitions.iter().find(|t| t.path().leaf() == o.path().leaf());
+
+    let mut users: Vec<User> = vec![];
+    for t in &ts.transitions {
+        users.push(User { name: t.path().leaf(), anchor: section("transition", t.path()),
+                          reads: vec![t.guard()], writes: t.actions() });
+    }
+    for o in &m.operations {
+        let (reads, writes) = match op_transition(o) {
+            Some(t) => (vec![t.guard(), o.goal()], t.actions()),
+            None => (vec![o.goal()], &[][..]),
+        };
+        users.push(User { name: o.path().leaf(), anchor: section("operation", o.path()), reads, writes });
+    }
+    for s in &ts.specs {
+        users.push(User { name: s.path().leaf(), anchor: section("invariant", s.path()),
+                          reads: vec![s.invariant()], writes: &[] });
+    }
+    for i in &m.intentions {
+        users.push(User { name: i.path().leaf(), anchor: section("intention", i.path()),
+                          reads: vec![i.goal()], writes: &[] });
+    }
+    let used = usages(&users);
+    let used_by = |v: &SPPath| match used.get(v) {
+        Some(names) => names.join(", "),
+        None => "unused".into(),
+    };
+
+    let mut out = String::new();
+    writeln!(out, "# {}\n", m.path()).unwrap();
+    writeln!(out, "{} resources, {} operations, {} intentions, {} invariants.\n",
+             m.resources.len(), m.operations.len(), m.intentions.len(), ts.specs.len()).unwrap();
+
+    writeln!(out, "## Resources\n").unwrap();
+    for r in &m.resources {
+        let prefix = r.path().to_string();
+        writeln!(out, "### {}\n", r.path().leaf()).unwrap();
+
+        writeln!(out, "| variable | value type | type | domain | initial | used by |").unwrap();
+        writeln!(out, "|---|---|---|---|---|---|").unwrap();
+        for v in ts.vars.iter().filter(|v| v.path().to_string().starts_with(&prefix)) {
+            let domain = v.domain().iter().map(|d| d.to_string()).collect::<Vec<_>>().join(", ");
+            let initial = initial.sp_value_from_path(v.path()).map(|v| v.to_string()).unwrap_or_default();
+            writeln!(out, "| <a id=\"{}\"></a>`{}` | {:?} | {:?} | {} | {} | {} |",
+                     anchor(v.path()), v.path().leaf(), v.value_type(), v.variable_type(),
+                     domain, initial, used_by(v.path())).unwrap();
+        }
+        out.push('\n');
+
+        if !r.messages.is_empty() {
+            writeln!(out, "| message | category | topic | type | variables |").unwrap();
+            writeln!(out, "|---|---|---|---|---|").unwrap();
+            for msg in &r.messages {
+                let vars = msg.variables.iter().map(|v| link(&v.path)).collect::<Vec<_>>().join(", ");
+                writeln!(out, "| {} | {:?} | `{}` | {:?} | {} |",
+                         msg.name, msg.category, msg.topic, msg.msg_type, vars).unwrap();
+            }
+            out.push('\n');
+        }
+
+        for t in &r.transitions {
+            transition(&mut out, &t.path().leaf(), &section("transition", t.path()), &format!("{:?}", t.type_),
+                       t.guard(), t.actions());
+        }
+    }
+
+    let others: Vec<_> = ts.vars.iter()
+        .filter(|v| !m.resources.iter().any(|r| v.path().to_string().starts_with(&r.path().to_string())))
+        .collect();
+    if !others.is_empty() {
+        writeln!(out, "## Model variables\n").unwrap();
+        writeln!(out, "| variable | domain | initial | used by |").unwrap();
+        writeln!(out, "|---|---|---|---|").unwrap();
+        for v in others {
+            let domain = v.domain().iter().map(|d| d.to_string()).collect::<Vec<_>>().join(", ");
+            let initial = initial.sp_value_from_path(v.path()).map(|v| v.to_string()).unwrap_or_default();
+            writeln!(out, "| <a id=\"{}\"></a>`{}` | {} | {} | {} |",
+                     anchor(v.path()), v.path(), domain, initial, used_by(v.path())).unwrap();
+        }
+        out.push('\n');
+    }
+
+    writeln!(out, "## Operations\n").unwrap();
+    for o in &m.operations {
+        let name = o.path().leaf();
+        match op_transition(o) {
+            Some(t) => transition(&mut out, &name, &section("operation", o.path()), "operation",
+                                  t.guard(), t.actions()),
+            None => writeln!(out, "#### {} <a id=\"{}\"></a>\n", name, section("operation", o.path())).unwrap(),
+        }
+        writeln!(out, "- goal: `{}`\n", o.goal()).unwrap();
+    }
+
+    writeln!(out, "## Invariants\n").unwrap();
+    for s in &ts.specs {
+        writeln!(out, "- <a id=\"{}\"></a>{}: `{}`", section("invariant", s.path()), s.path().leaf(), s.invariant()).unwrap();
+    }
+    out.push('\n');
+
+    writeln!(out, "## Intentions\n").unwrap();
+    for i in &m.intentions {
+        writeln!(out, "- <a id=\"{}\"></a>{}: goal `{}`", section("intention", i.path()), i.path().leaf(), i.goal()).unwrap();
+    }
+    out
+}
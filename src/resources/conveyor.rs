@@ -0,0 +1,209 @@
+use sp_domain::*;
+use super::plc::{Handshake, PLCResource};
+
+/// A conveyor bringing parts to and from a part-present sensor at
+/// its end.
+///
+/// The commands and the sensor are PLC tags and live in the PLC
+/// resource, the conveyor resource holds the transitions on top of
+/// them.
+pub struct Conveyor {
+    pub path: SPPath,
+    pub part_present: SPPath, // Measured. the sensor at the end
+    pub initial_state: SPState,
+    to_sensor: (Predicate, Vec<Action>, Predicate), // low level goal, actions and failure
+    from_sensor: (Predicate, Vec<Action>, Predicate),
+}
+
+// When we drive the conveyor directly:
+//
+// run && !reverse && !part_present -> part_present
+// run && reverse && part_present -> !part_present
+//
+// When the PLC program drives it, the requests of the handshakes
+// take the place of run and reverse.
+//
+impl Conveyor {
+    /// Drive the conveyor through run/reverse outputs and read the
+    /// part sensor from the PLC.
+    pub fn direct(model: &mut Model, name: &str, plc: &PLCResource,
+                  run: &str, reverse: &str, part_present: &str) -> Conveyor {
+        let run = plc.tag(run);
+        let reverse = plc.tag(reverse);
+        let part_present = plc.tag(part_present);
+
+        let path = model.add_resource(name);
+        let r = model.get_resource(&path);
+
+        r.add_transition(Transition::new(
+            &format!("{}_start_forward", name),
+            p!(!run),
+            Predicate::TRUE,
+            vec![a!(run), a!(!reverse)],
+            vec![],
+            TransitionType::Controlled));
+
+        r.add_transition(Transition::new(
+            &format!("{}_start_reverse", name),
+            p!(!run),
+            Predicate::TRUE,
+            vec![a!(run), a!(reverse)],
+            vec![],
+            TransitionType::Controlled));
+
+        r.add_transition(Transition::new(
+            &format!("{}_stop", name),
+            p!(run),
+            Predicate::TRUE,
+            vec![a!(!run)],
+            vec![],
+            TransitionType::Controlled));
+
+        Conveyor::add_sensor_effects(r, name, &part_present, p!([run] && [!reverse]), p!([run] && [reverse]));
+
+        Conveyor {
+            path,
+            initial_state: SPState::new(),
+            to_sensor: (p!([part_present] && [!run]), vec![], Predicate::FALSE),
+            from_sensor: (p!([!part_present] && [!run]), vec![], Predicate::FALSE),
+            part_present,
+        }
+    }
+
+    /// The PLC program runs the conveyor itself, we only request a
+    /// part to or from the sensor.
+    pub fn with_handshakes(model: &mut Model, name: &str, plc: &PLCResource,
+                           to_sensor: &Handshake, from_sensor: &Handshake,
+                           part_present: &str) -> Conveyor {
+        let part_present = plc.tag(part_present);
+        let loading = plc.tag(&to_sensor.request);
+        let unloading = plc.tag(&from_sensor.request);
+
+        let to_sensor = plc.add_handshake(model, to_sensor);
+        let from_sensor = plc.add_handshake(model, from_sensor);
+
+        let path = model.add_resource(name);
+        let r = model.get_resource(&path);
+        Conveyor::add_sensor_effects(r, name, &part_present, p!(loading), p!(unloading));
+
+        let mut initial_state = to_sensor.initial_state;
+        initial_state.extend(from_sensor.initial_state);
+
+        let to_goal = to_sensor.goal;
+        let from_goal = from_sensor.goal;
+        Conveyor {
+            path,
+            initial_state,
+            to_sensor: (p!([p: to_goal] && [part_present]), to_sensor.actions, to_sensor.failed),
+            from_sensor: (p!([p: from_goal] && [!part_present]), from_sensor.actions, from_sensor.failed),
+            part_present,
+        }
+    }
+
+    fn add_sensor_effects(r: &mut Resource, name: &str, part_present: &SPPath,
+                          forward: Predicate, reverse: Predicate) {
+        r.add_transition(Transition::new(
+            &format!("{}_part_arrives", name),
+            p!([p: forward] && [!part_present]),
+            Predicate::TRUE,
+            vec![a!(part_present)],
+            vec![],
+            TransitionType::Effect));
+
+        r.add_transition(Transition::new(
+            &format!("{}_part_leaves", name),
+            p!([p: reverse] && [part_present]),
+            Predicate::TRUE,
+            vec![a!(!part_present)],
+            vec![],
+            TransitionType::Effect));
+    }
+
+    /// Operation bringing a part to the sensor. The guard and effects
+    /// describe the product state.
+    pub fn add_to_sensor_op(&self, model: &mut Model, name: &str,
+                            guard: &Predicate, effects: &[Action]) -> SPPath {
+        self.add_op(model, name, guard, effects, &self.to_sensor)
+    }
+
+    /// Operation taking a part away from the sensor.
+    pub fn add_from_sensor_op(&self, model: &mut Model, name: &str,
+                              guard: &Predicate, effects: &[Action]) -> SPPath {
+        self.add_op(model, name, guard, effects, &self.from_sensor)
+    }
+
+    // a failed handshake resets the operation without its effects,
+    // so that it can be tried again.
+    fn add_op(&self, model: &mut Model, name: &str, guard: &Predicate, effects: &[Action],
+              (goal, actions, failed): &(Predicate, Vec<Action>, Predicate)) -> SPPath {
+        let op = model.add_op(name, guard, effects, goal, actions, false, None);
+        if failed != &Predicate::FALSE {
+            let mut reset = actions.clone();
+            reset.push(a!(op <- "i"));
+            model.get_resource(&self.path).add_transition(Transition::new(
+                &format!("{}_failed", name),
+                p!([op == "e"] && [p: failed]),
+                Predicate::TRUE,
+                reset,
+                vec![],
+                TransitionType::Auto));
+        }
+        op
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resources::interface::RosInterface;
+    use crate::resources::plc::{OpcNodeIds, PlcTag};
+
+    fn make_conveyor(m: &mut Model) -> (PLCResource, Conveyor) {
+        let tags = [
+            PlcTag::bool_to_plc("run", "bool_to_plc_1"),
+            PlcTag::bool_to_plc("reverse", "bool_to_plc_2"),
+            PlcTag::bool_from_plc("part_present", "bool_from_plc_1"),
+        ];
+        let r = m.add_resource("plc");
+        let plc = PLCResource::new(m.get_resource(&r), &RosInterface::default(),
+                                   &OpcNodeIds::default(), &tags);
+        let conveyor = Conveyor::direct(m, "conveyor", &plc, "run", "reverse", "part_present");
+        (plc, conveyor)
+    }
+
+    #[test]
+    fn direct_transitions_belong_to_the_conveyor() {
+        let mut m = Model::new("test");
+        let (plc, conveyor) = make_conveyor(&mut m);
+        let names: Vec<String> = m.get_resource(&conveyor.path).transitions.iter()
+            .map(|t| t.path().leaf()).collect();
+        assert_eq!(names, vec!["conveyor_start_forward", "conveyor_start_reverse", "conveyor_stop",
+                               "conveyor_part_arrives", "conveyor_part_leaves"]);
+        assert!(m.get_resource(&plc.path).transitions.is_empty());
+    }
+
+    #[test]
+    fn direct_part_arrives_when_running_forward() {
+        let mut m = Model::new("test");
+        let (plc, conveyor) = make_conveyor(&mut m);
+        let r = m.get_resource(&conveyor.path);
+        let arrives = r.transitions.iter().find(|t| t.path().leaf() == "conveyor_part_arrives").unwrap();
+
+        let state = |run: bool, reverse: bool| SPState::new_from_values(&[
+            (plc.tag("run"), run.to_spvalue()),
+            (plc.tag("reverse"), reverse.to_spvalue()),
+            (conveyor.part_present.clone(), false.to_spvalue()),
+        ]);
+        assert!(arrives.eval(&state(true, false)));
+        assert!(!arrives.eval(&state(true, true)));
+        assert!(!arrives.eval(&state(false, false)));
+    }
+
+    #[test]
+    fn direct_op_keeps_its_name() {
+        let mut m = Model::new("test");
+        let (_, conveyor) = make_conveyor(&mut m);
+        let op = conveyor.add_to_sensor_op(&mut m, "cylinder_to_sensor", &Predicate::TRUE, &[]);
+        assert_eq!(op.leaf(), "cylinder_to_sensor");
+    }
+}
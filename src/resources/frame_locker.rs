@@ -1,83 +1,360 @@
 use sp_domain::*;
+use super::interface::RosInterface;
+use std::collections::HashMap;
+
+pub struct LockableFrame {
+    pub exists: SPPath, // boolean
+    pub locked: SPPath, // boolean
+    pub fresh: SPPath, // boolean, pose in the tf tree is recent enough
+    pub parent: SPPath, // Measured. parent frame in the tf tree
+    pub is_locking: SPPath, // predicates
+    pub is_unlocking: SPPath,
+    pub is_stale: SPPath,
+}
+
+/// The lock and unlock services of the frame locker node.
+///
+/// The existing node serves std_srvs/Trigger on /lock_frames, which
+/// locks all frames at once, and the model locks them all together.
+/// If the node takes the frame to (un)lock in the request, set
+/// `frame_id_field` and the service type accordingly, and each frame
+/// is locked on its own.
+pub struct LockServices {
+    pub lock: String,
+    pub unlock: String,
+    pub service_type: String,
+    pub frame_id_field: Option<String>,
+}
+
+impl Default for LockServices {
+    fn default() -> Self {
+        LockServices {
+            lock: "/lock_frames".into(),
+            unlock: "/unlock_frames".into(),
+            service_type: "std_srvs/srv/Trigger".into(),
+            frame_id_field: None,
+        }
+    }
+}
 
 pub struct FrameLocker {
     pub path: SPPath,
-    pub frame_exists: SPPath, // boolean
-    pub frame_locked: SPPath, // boolean
-    pub is_locking: SPPath,
+    pub frame_id: SPPath, // Command. the frame to lock or unlock, domain is the frame ids
+    pub frames: HashMap<String, LockableFrame>,
     pub initial_state: SPState,
 }
 
+// Locking goes through the same lock/unlock services for all frames.
+// With a frame id field in the request, the frame id command variable
+// selects the frame, without one a call (un)locks all of them. Each
+// frame id has its own exists/locked topics, e.g. /frame_locked/<id>.
+//
+// Freshness and parent frame come from /frame_fresh/<id> and
+// /frame_parent/<id>, published by `watchdog::run_frame_watchdog`
+// which looks the frames up in the scene manipulation tf tree and
+// compares the pose stamp to the max age.
+//
+// The state machine per frame:
+//
+// lock && !locked -> locked (all frames when locking together)
+// unlock && locked -> !locked
+// exists && !locked && !fresh -> fresh (effect, the marker is detected)
+// fresh && !locked -> !fresh (effect, the pose gets too old)
+//
+// A locked frame is static, its stamp is not refreshed and the
+// watchdog soon reports it as old. Its pose stays valid though, so a
+// locked frame counts as fresh.
+//
 impl FrameLocker {
-    pub fn new(r: &mut Resource) -> FrameLocker {
-        let _name = r.path().leaf();
+    pub fn new(r: &mut Resource, interface: &RosInterface, services: &LockServices,
+               frame_ids: &[&str]) -> FrameLocker {
+        let name = r.path().leaf();
+        assert!(!frame_ids.is_empty(), "{} needs at least one frame id", name);
+        let by_id = services.frame_id_field.is_some();
 
-        let lock_trigger = r.add_variable(Variable::new_boolean("lock_trigger", VariableType::Command));
+        let mut frames = HashMap::new();
 
-        let frame_exists = r.add_variable(
-            Variable::new_boolean("frame_exists", VariableType::Measured));
-        let frame_locked = r.add_variable(
-            Variable::new_boolean("frame_locked", VariableType::Measured));
+        let lock_trigger = r.add_variable(Variable::new_boolean("lock_trigger", VariableType::Command));
+        let unlock_trigger = r.add_variable(Variable::new_boolean("unlock_trigger", VariableType::Command));
+        let ids: Vec<SPValue> = frame_ids.iter().map(|id| id.to_spvalue()).collect();
+        let frame_id = r.add_variable(Variable::new(
+            "frame_id", VariableType::Command, SPValueType::String, ids));
 
-        // setup the service
+        let request: Vec<MessageVariable> = services.frame_id_field.iter()
+            .map(|field| MessageVariable::new(&frame_id, field))
+            .collect();
         let lock_service = r.setup_ros_service(
-            "lock_frame",
-            "/lock_frames",
-            "std_srvs/srv/Trigger",
-            p!(lock_trigger), &[], &[]);
+            "lock_frames",
+            &interface.resolve(&services.lock),
+            &services.service_type,
+            p!(lock_trigger), &request, &[]);
+        let unlock_service = r.setup_ros_service(
+            "unlock_frames",
+            &interface.resolve(&services.unlock),
+            &services.service_type,
+            p!(unlock_trigger), &request, &[]);
 
-        r.setup_ros_incoming("frame_exists", "/frame_exists",
-                                    MessageType::Ros("std_msgs/msg/Bool".into()),
-            &[
-                MessageVariable::new(&frame_exists, "data"),
-            ]);
-        r.setup_ros_incoming("frame_exists", "/frame_locked",
-                                    MessageType::Ros("std_msgs/msg/Bool".into()),
-            &[
-                MessageVariable::new(&frame_locked, "data"),
-            ]);
-
-        r.add_transition(Transition::new(
-            &format!("{}_lock", &r.path().leaf()),
-            p!([lock_service == "ok"] && [!lock_trigger] && [frame_exists] && [!frame_locked]),
-            Predicate::TRUE,
-            vec![a!(lock_trigger)],
-            vec![],
-            TransitionType::Controlled));
-
-        r.add_transition(Transition::new(
-            &format!("{}_lock_done", &r.path().leaf()),
-            p!([lock_trigger] && [!frame_locked]),
-            Predicate::TRUE,
-            vec![a!(frame_locked)],
-            vec![],
-            TransitionType::Effect));
-
-        r.add_transition(Transition::new(
-            &format!("{}_lock_reset", &r.path().leaf()),
-            p!([lock_trigger] && [frame_locked]),
-            Predicate::TRUE,
-            vec![a!(!lock_trigger)],
-            vec![],
-            TransitionType::Auto));
-
-        let initial_state = SPState::new_from_values(
+        let mut initial_state = SPState::new_from_values(
             &[
                 (lock_trigger.clone(), false.to_spvalue()),
+                (unlock_trigger.clone(), false.to_spvalue()),
+                (frame_id.clone(), frame_ids[0].to_spvalue()),
             ]);
 
-        let is_locking = Variable::new_predicate("is_locking", p!([lock_trigger] &&
-                                                                  [!frame_locked]));
-        let is_locking = r.add_variable(is_locking);
+        for id in frame_ids {
+            let id = *id;
+            let exists = r.add_variable(Variable::new_boolean(
+                &format!("{}/exists", id), VariableType::Measured));
+            let locked = r.add_variable(Variable::new_boolean(
+                &format!("{}/locked", id), VariableType::Measured));
+
+            let fresh = r.add_variable(Variable::new_boolean(
+                &format!("{}/fresh", id), VariableType::Measured));
+            let parent = r.add_variable(Variable::new(
+                &format!("{}/parent", id), VariableType::Measured,
+                SPValueType::String, vec!()));
+
+            r.setup_ros_incoming(&format!("{}_exists", id),
+                                 &interface.resolve(&format!("/frame_exists/{}", id)),
+                                 MessageType::Ros("std_msgs/msg/Bool".into()),
+                &[
+                    MessageVariable::new(&exists, "data"),
+                ]);
+            r.setup_ros_incoming(&format!("{}_locked", id),
+                                 &interface.resolve(&format!("/frame_locked/{}", id)),
+                                 MessageType::Ros("std_msgs/msg/Bool".into()),
+                &[
+                    MessageVariable::new(&locked, "data"),
+                ]);
+            r.setup_ros_incoming(&format!("{}_fresh", id),
+                                 &interface.resolve(&format!("/frame_fresh/{}", id)),
+                                 MessageType::Ros("std_msgs/msg/Bool".into()),
+                &[
+                    MessageVariable::new(&fresh, "data"),
+                ]);
+            r.setup_ros_incoming(&format!("{}_parent", id),
+                                 &interface.resolve(&format!("/frame_parent/{}", id)),
+                                 MessageType::Ros("std_msgs/msg/String".into()),
+                &[
+                    MessageVariable::new(&parent, "data"),
+                ]);
+
+            if by_id {
+                r.add_transition(Transition::new(
+                    &format!("{}_lock_{}", name, id),
+                    p!([lock_service == "ok"] && [!lock_trigger] && [!unlock_trigger] &&
+                       [exists] && [!locked]),
+                    Predicate::TRUE,
+                    vec![a!(lock_trigger), a!(frame_id <- id)],
+                    vec![],
+                    TransitionType::Controlled));
+
+                r.add_transition(Transition::new(
+                    &format!("{}_lock_{}_done", name, id),
+                    p!([lock_trigger] && [frame_id == id] && [!locked]),
+                    Predicate::TRUE,
+                    vec![a!(locked)],
+                    vec![],
+                    TransitionType::Effect));
+
+                r.add_transition(Transition::new(
+                    &format!("{}_lock_{}_reset", name, id),
+                    p!([lock_trigger] && [frame_id == id] && [locked]),
+                    Predicate::TRUE,
+                    vec![a!(!lock_trigger)],
+                    vec![],
+                    TransitionType::Auto));
+
+                r.add_transition(Transition::new(
+                    &format!("{}_unlock_{}", name, id),
+                    p!([unlock_service == "ok"] && [!unlock_trigger] && [!lock_trigger] && [locked]),
+                    Predicate::TRUE,
+                    vec![a!(unlock_trigger), a!(frame_id <- id)],
+                    vec![],
+                    TransitionType::Controlled));
+
+                r.add_transition(Transition::new(
+                    &format!("{}_unlock_{}_done", name, id),
+                    p!([unlock_trigger] && [frame_id == id] && [locked]),
+                    Predicate::TRUE,
+                    vec![a!(!locked)],
+                    vec![],
+                    TransitionType::Effect));
+
+                r.add_transition(Transition::new(
+                    &format!("{}_unlock_{}_reset", name, id),
+                    p!([unlock_trigger] && [frame_id == id] && [!locked]),
+                    Predicate::TRUE,
+                    vec![a!(!unlock_trigger)],
+                    vec![],
+                    TransitionType::Auto));
+            }
+
+            r.add_transition(Transition::new(
+                &format!("{}_{}_detected", name, id),
+                p!([exists] && [!locked] && [!fresh]),
+                Predicate::TRUE,
+                vec![a!(fresh)],
+                vec![],
+                TransitionType::Effect));
+
+            r.add_transition(Transition::new(
+                &format!("{}_{}_aged", name, id),
+                p!([fresh] && [!locked]),
+                Predicate::TRUE,
+                vec![a!(!fresh)],
+                vec![],
+                TransitionType::Effect));
+
+            let selected = if by_id { p!(frame_id == id) } else { Predicate::TRUE };
+            let is_locking = Variable::new_predicate(&format!("{}/is_locking", id),
+                                                     p!([lock_trigger] && [p: selected] && [!locked]));
+            let is_locking = r.add_variable(is_locking);
+
+            let is_unlocking = Variable::new_predicate(&format!("{}/is_unlocking", id),
+                                                       p!([unlock_trigger] && [p: selected] && [locked]));
+            let is_unlocking = r.add_variable(is_unlocking);
 
+            let is_stale = Variable::new_predicate(&format!("{}/is_stale", id),
+                                                   p!([!exists] || [[!locked] && [!fresh]]));
+            let is_stale = r.add_variable(is_stale);
+
+            initial_state.extend(SPState::new_from_values(
+                &[
+                    (fresh.clone(), false.to_spvalue()),
+                ]));
+
+            frames.insert(id.to_string(), LockableFrame {
+                exists,
+                locked,
+                fresh,
+                parent,
+                is_locking,
+                is_unlocking,
+                is_stale,
+            });
+        }
+
+        if !by_id {
+            let frames: Vec<&LockableFrame> = frame_ids.iter().map(|id| &frames[*id]).collect();
+            add_lock_all(r, &lock_service, &unlock_service, &lock_trigger, &unlock_trigger, &frames);
+        }
 
         return FrameLocker {
             path: r.path().clone(),
-            frame_exists,
-            frame_locked,
-            is_locking,
+            frame_id,
+            frames,
             initial_state,
         }
     }
 
+    pub fn frame(&self, id: &str) -> &LockableFrame {
+        self.frames.get(id).unwrap_or_else(|| panic!("{} has no frame {}", self.path, id))
+    }
+
+    /// Guard for motions to frames that depend on `id`, e.g. frames
+    /// placed relative to a detected marker. Locked frames are fresh.
+    pub fn fresh(&self, id: &str) -> Predicate {
+        let f = self.frame(id);
+        p!([f.exists] && [[f.locked] || [f.fresh]])
+    }
+}
+
+/// Lock and unlock transitions for a node that (un)locks all frames
+/// with one call.
+fn add_lock_all(r: &mut Resource, lock_service: &SPPath, unlock_service: &SPPath,
+                lock_trigger: &SPPath, unlock_trigger: &SPPath, frames: &[&LockableFrame]) {
+    let name = r.path().leaf();
+    let exists: Vec<SPPath> = frames.iter().map(|f| f.exists.clone()).collect();
+    let locked: Vec<SPPath> = frames.iter().map(|f| f.locked.clone()).collect();
+    let any_unlocked = Predicate::OR(exists.iter().zip(&locked).map(|(e, l)| p!([e] && [!l])).collect());
+    let any_locked = Predicate::OR(locked.iter().map(|l| p!(l)).collect());
+    let all_locked = Predicate::AND(locked.iter().map(|l| p!(l)).collect());
+    let none_locked = Predicate::AND(locked.iter().map(|l| p!(!l)).collect());
+    let not_all_locked = Predicate::NOT(Box::new(all_locked.clone()));
+
+    r.add_transition(Transition::new(
+        &format!("{}_lock", name),
+        p!([lock_service == "ok"] && [!lock_trigger] && [!unlock_trigger] && [p: any_unlocked]),
+        Predicate::TRUE,
+        vec![a!(lock_trigger)],
+        vec![],
+        TransitionType::Controlled));
+
+    r.add_transition(Transition::new(
+        &format!("{}_lock_done", name),
+        p!([lock_trigger] && [p: not_all_locked]),
+        Predicate::TRUE,
+        locked.iter().map(|l| a!(l)).collect(),
+        vec![],
+        TransitionType::Effect));
+
+    r.add_transition(Transition::new(
+        &format!("{}_lock_reset", name),
+        p!([lock_trigger] && [p: all_locked]),
+        Predicate::TRUE,
+        vec![a!(!lock_trigger)],
+        vec![],
+        TransitionType::Auto));
+
+    r.add_transition(Transition::new(
+        &format!("{}_unlock", name),
+        p!([unlock_service == "ok"] && [!unlock_trigger] && [!lock_trigger] && [p: any_locked]),
+        Predicate::TRUE,
+        vec![a!(unlock_trigger)],
+        vec![],
+        TransitionType::Controlled));
+
+    r.add_transition(Transition::new(
+        &format!("{}_unlock_done", name),
+        p!([unlock_trigger] && [p: any_locked]),
+        Predicate::TRUE,
+        locked.iter().map(|l| a!(!l)).collect(),
+        vec![],
+        TransitionType::Effect));
+
+    r.add_transition(Transition::new(
+        &format!("{}_unlock_reset", name),
+        p!([unlock_trigger] && [p: none_locked]),
+        Predicate::TRUE,
+        vec![a!(!unlock_trigger)],
+        vec![],
+        TransitionType::Auto));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Names of the transitions of a frame locker.
+    fn make_locker(services: &LockServices, frame_ids: &[&str]) -> Vec<String> {
+        let mut m = Model::new("test");
+        let r = m.add_resource("frame_locker");
+        FrameLocker::new(m.get_resource(&r), &RosInterface::default(), services, frame_ids);
+        m.get_resource(&r).transitions.iter().map(|t| t.path().leaf()).collect()
+    }
+
+    #[test]
+    fn trigger_services_lock_all_frames_together() {
+        let names = make_locker(&LockServices::default(), &["aruco", "fixture"]);
+        assert!(names.contains(&"frame_locker_lock".to_string()), "{:?}", names);
+        assert!(!names.iter().any(|n| n.starts_with("frame_locker_lock_aruco")), "{:?}", names);
+    }
+
+    #[test]
+    fn frame_id_field_locks_each_frame() {
+        let services = LockServices {
+            frame_id_field: Some("frame_id".into()),
+            ..LockServices::default()
+        };
+        let names = make_locker(&services, &["aruco", "fixture"]);
+        assert!(names.contains(&"frame_locker_lock_aruco".to_string()), "{:?}", names);
+        assert!(names.contains(&"frame_locker_unlock_fixture".to_string()), "{:?}", names);
+    }
+
+    #[test]
+    #[should_panic(expected = "needs at least one frame id")]
+    fn no_frame_ids_panics() {
+        make_locker(&LockServices::default(), &[]);
+    }
 }
@@ -0,0 +1,46 @@
+use sp_domain::*;
+
+/// What the rest of a model needs to know about an end effector.
+///
+/// Invariants and operation goals should be written against these
+/// paths so that the gripper can be swapped without touching them.
+pub trait Gripper {
+    fn path(&self) -> &SPPath;
+
+    /// Raw measured state of the gripper. The domain depends on the
+    /// implementation.
+    fn measured(&self) -> &SPPath;
+
+    /// Predicate, true while the gripper is closing/sucking.
+    fn is_closing(&self) -> &SPPath;
+
+    /// Predicate, true while the gripper is opening/releasing.
+    fn is_opening(&self) -> &SPPath;
+
+    /// Predicate, true when the gripper holds a part.
+    fn holding_part(&self) -> &SPPath;
+
+    /// Predicate, true when the gripper is fully released.
+    fn released(&self) -> &SPPath;
+
+    /// Predicate, true when the gripper gave up on holding a part
+    /// that was expected to be there.
+    fn part_missing(&self) -> &SPPath;
+
+    fn initial_state(&self) -> &SPState;
+
+    /// Auto transition for when an expected part is missing. Use the
+    /// guard and actions to put the product state back so that the
+    /// planner can try again.
+    fn add_part_missing_recovery(&self, m: &mut Model, name: &str,
+                                 guard: Predicate, actions: Vec<Action>) {
+        let part_missing = self.part_missing();
+        m.add_transition(Transition::new(
+            name,
+            p!([part_missing] && [p: guard]),
+            Predicate::TRUE,
+            actions,
+            vec![],
+            TransitionType::Auto));
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+/// ROS names used by a resource.
+///
+/// Resources pass their default names (e.g. "/robotiq_2f_open")
+/// through `resolve`, which first applies any remapping and then
+/// puts the result under the namespace. Like in ROS, an absolute
+/// remapping target ("/other") is used as it is. The default
+/// interface keeps the names as they are.
+#[derive(Debug, Clone, Default)]
+pub struct RosInterface {
+    pub namespace: Option<String>,
+    pub remappings: HashMap<String, String>,
+}
+
+impl RosInterface {
+    pub fn with_namespace(namespace: &str) -> RosInterface {
+        RosInterface {
+            namespace: Some(namespace.trim_matches('/').to_string()),
+            ..RosInterface::default()
+        }
+    }
+
+    pub fn remap(mut self, from: &str, to: &str) -> RosInterface {
+        self.remappings.insert(from.to_string(), to.to_string());
+        self
+    }
+
+    pub fn resolve(&self, name: &str) -> String {
+        let name = match self.remappings.get(name) {
+            Some(to) if to.starts_with('/') => return to.clone(),
+            Some(to) => to.as_str(),
+            None => name,
+        };
+        let name = name.trim_start_matches('/');
+        match &self.namespace {
+            Some(ns) if !ns.is_empty() => format!("/{}/{}", ns, name),
+            _ => format!("/{}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default_keeps_names() {
+        assert_eq!(RosInterface::default().resolve("/lock_frames"), "/lock_frames");
+    }
+
+    #[test]
+    fn namespace_is_prepended() {
+        let interface = RosInterface::with_namespace("/cell_2/");
+        assert_eq!(interface.resolve("/robotiq_2f_open"), "/cell_2/robotiq_2f_open");
+    }
+
+    #[test]
+    fn relative_remap_is_namespaced() {
+        let interface = RosInterface::with_namespace("cell_2")
+            .remap("/robotiq_2f_open", "left_gripper_open");
+        assert_eq!(interface.resolve("/robotiq_2f_open"), "/cell_2/left_gripper_open");
+        assert_eq!(interface.resolve("/robotiq_2f_close"), "/cell_2/robotiq_2f_close");
+    }
+
+    #[test]
+    fn absolute_remap_is_kept() {
+        let interface = RosInterface::with_namespace("cell_2")
+            .remap("/ur_control", "/shared/ur_control");
+        assert_eq!(interface.resolve("/ur_control"), "/shared/ur_control");
+    }
+}
@@ -1,4 +1,9 @@
+pub mod interface;
 pub mod ur;
 pub mod plc;
+pub mod modbus;
+pub mod conveyor;
+pub mod gripper;
 pub mod robotiq_gripper;
+pub mod vacuum_gripper;
 pub mod frame_locker;
@@ -0,0 +1,38 @@
+use super::plc::{PlcNode, PlcTag, PlcType, TagKeys};
+
+/// Where a tag lives on a Modbus server. Bools are coils and ints
+/// are holding registers, the numeric tag node is the address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModbusRegister {
+    Coil(u16),
+    HoldingRegister(u16),
+}
+
+impl ModbusRegister {
+    pub fn of(tag: &PlcTag) -> ModbusRegister {
+        let address = match &tag.node {
+            PlcNode::Numeric(address) if *address <= u16::MAX as u32 => *address as u16,
+            node => panic!("modbus tag {} needs a 16 bit address, got {:?}", tag.name, node),
+        };
+        match tag.value_type {
+            PlcType::Bool => ModbusRegister::Coil(address),
+            PlcType::Int => ModbusRegister::HoldingRegister(address),
+            _ => panic!("modbus tag {} must be bool or int", tag.name),
+        }
+    }
+}
+
+/// Keys for a PLCResource served by crate::modbus::run_modbus_bridge
+/// instead of an OPC UA server, e.g.
+///
+/// PLCResource::new(r, &interface, &ModbusKeys, &tags)
+pub struct ModbusKeys;
+
+impl TagKeys for ModbusKeys {
+    fn key(&self, tag: &PlcTag) -> String {
+        match ModbusRegister::of(tag) {
+            ModbusRegister::Coil(address) => format!("coil/{}", address),
+            ModbusRegister::HoldingRegister(address) => format!("hr/{}", address),
+        }
+    }
+}
@@ -1,131 +1,609 @@
 use sp_domain::*;
+use super::interface::RosInterface;
+use crate::watchdog::HandshakeTimer;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Where a tag lives in the OPC UA address space.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlcNode {
+    Name(String), // string identifier below the application path
+    Numeric(u32),
+}
+
+impl From<&str> for PlcNode {
+    fn from(name: &str) -> Self {
+        PlcNode::Name(name.into())
+    }
+}
+
+impl From<u32> for PlcNode {
+    fn from(id: u32) -> Self {
+        PlcNode::Numeric(id)
+    }
+}
+
+/// How node ids are formed for the PLC we talk to.
+///
+/// The template is used for named nodes and may contain `{ns}`,
+/// `{app}` and `{name}`. Numeric nodes always become `ns=<ns>;i=<id>`.
+#[derive(Debug, Clone)]
+pub struct OpcNodeIds {
+    pub namespace: u16,
+    pub application_path: String,
+    pub template: String,
+}
+
+impl Default for OpcNodeIds {
+    fn default() -> Self {
+        OpcNodeIds {
+            namespace: 4,
+            application_path: "|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO".into(),
+            template: "ns={ns};s={app}.{name}".into(),
+        }
+    }
+}
+
+impl OpcNodeIds {
+    pub fn node_id(&self, node: &PlcNode) -> String {
+        match node {
+            PlcNode::Name(name) => self.template
+                .replace("{ns}", &self.namespace.to_string())
+                .replace("{app}", &self.application_path)
+                .replace("{name}", name),
+            PlcNode::Numeric(id) => format!("ns={};i={}", self.namespace, id),
+        }
+    }
+}
+
+/// How tags are keyed in the json messages exchanged with the bridge
+/// to the PLC. The OPC UA bridge uses node ids, the Modbus bridge in
+/// crate::modbus uses registers, see `modbus::ModbusKeys`.
+pub trait TagKeys {
+    fn key(&self, tag: &PlcTag) -> String;
+}
+
+impl TagKeys for OpcNodeIds {
+    fn key(&self, tag: &PlcTag) -> String {
+        self.node_id(&tag.node)
+    }
+}
+
+/// Key of the tag called `name`, for the tools that run next to the
+/// model and need to find a tag in the bridge messages.
+pub fn tag_key(tags: &[PlcTag], keys: &dyn TagKeys, name: &str) -> String {
+    tags.iter().find(|t| t.name == name)
+        .map(|t| keys.key(t))
+        .unwrap_or_else(|| panic!("no PLC tag {}", name))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlcDirection {
+    ToPlc,
+    FromPlc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlcType {
+    Bool,
+    Int,
+    Real,
+    String,
+}
+
+/// A named variable exchanged with the PLC.
+#[derive(Debug, Clone)]
+pub struct PlcTag {
+    pub name: String,
+    pub direction: PlcDirection,
+    pub value_type: PlcType,
+    pub domain: Vec<SPValue>, // empty for bools and unrestricted values
+    pub node: PlcNode,
+    pub states: Vec<(i32, String)>, // symbolic names of int values, see `enumerated`
+}
+
+impl PlcTag {
+    pub fn new(name: &str, direction: PlcDirection, value_type: PlcType,
+               domain: Vec<SPValue>, node: impl Into<PlcNode>) -> PlcTag {
+        PlcTag {
+            name: name.into(),
+            direction,
+            value_type,
+            domain,
+            node: node.into(),
+            states: vec![],
+        }
+    }
+
+    pub fn bool_to_plc(name: &str, node: impl Into<PlcNode>) -> PlcTag {
+        PlcTag::new(name, PlcDirection::ToPlc, PlcType::Bool, vec![], node)
+    }
+
+    pub fn bool_from_plc(name: &str, node: impl Into<PlcNode>) -> PlcTag {
+        PlcTag::new(name, PlcDirection::FromPlc, PlcType::Bool, vec![], node)
+    }
+
+    pub fn int_to_plc(name: &str, node: impl Into<PlcNode>, domain: Vec<SPValue>) -> PlcTag {
+        PlcTag::new(name, PlcDirection::ToPlc, PlcType::Int, domain, node)
+    }
+
+    pub fn int_from_plc(name: &str, node: impl Into<PlcNode>, domain: Vec<SPValue>) -> PlcTag {
+        PlcTag::new(name, PlcDirection::FromPlc, PlcType::Int, domain, node)
+    }
+
+    /// An int tag holding the state of a state machine in the PLC,
+    /// e.g. [(0, "idle"), (1, "running"), (2, "fault")]. The first
+    /// state is the initial one.
+    pub fn enumerated(name: &str, direction: PlcDirection, node: impl Into<PlcNode>,
+                      states: &[(i32, &str)]) -> PlcTag {
+        let domain = states.iter().map(|(v, _)| v.to_spvalue()).collect();
+        let mut tag = PlcTag::new(name, direction, PlcType::Int, domain, node);
+        tag.states = states.iter().map(|(v, s)| (*v, s.to_string())).collect();
+        tag
+    }
+
+    pub(crate) fn variable(&self) -> Variable {
+        let (path, variable_type) = match self.direction {
+            PlcDirection::ToPlc => (format!("command/{}", self.name), VariableType::Command),
+            PlcDirection::FromPlc => (format!("measured/{}", self.name), VariableType::Measured),
+        };
+        let value_type = match self.value_type {
+            PlcType::Bool => return Variable::new_boolean(&path, variable_type),
+            PlcType::Int => SPValueType::Int32,
+            PlcType::Real => SPValueType::Float32,
+            PlcType::String => SPValueType::String,
+        };
+        Variable::new(&path, variable_type, value_type, self.domain.clone())
+    }
+
+    /// A command at rest, false or its initial value, and the action
+    /// putting it there.
+    pub(crate) fn idle(&self, path: &SPPath) -> (Predicate, Action) {
+        match self.value_type {
+            PlcType::Bool => (p!(!path), a!(!path)),
+            _ => {
+                let initial = self.initial_value();
+                (p!(path == initial), a!(path <- initial))
+            }
+        }
+    }
+
+    pub(crate) fn initial_value(&self) -> SPValue {
+        if let Some(v) = self.domain.first() {
+            return v.clone();
+        }
+        match self.value_type {
+            PlcType::Bool => false.to_spvalue(),
+            PlcType::Int => 0.to_spvalue(),
+            PlcType::Real => 0.0.to_spvalue(),
+            PlcType::String => "".to_spvalue(),
+        }
+    }
+}
+
+/// A request/acknowledge exchange with the PLC: we raise `request`,
+/// the PLC answers with `done` and drops `done` again when we take
+/// the request down.
+///
+/// The exchange fails if the PLC raises `error`, answers with
+/// another `result` than the ok value, or does not answer within
+/// `timeout`. The timeout is measured on our side, by
+/// `watchdog::run_plc_watchdog`.
+#[derive(Debug, Clone)]
+pub struct Handshake {
+    pub name: String,
+    pub request: String, // bool tag to the PLC
+    pub done: String, // bool tag from the PLC
+    pub argument: Option<(String, SPValue)>, // tag to the PLC, written with the request
+    pub result: Option<(String, SPValue)>, // tag from the PLC and its success value
+    pub error: Option<String>, // bool tag from the PLC, raised when it gives up
+    pub timeout: Option<Duration>,
+}
+
+impl Handshake {
+    pub fn new(name: &str, request: &str, done: &str) -> Handshake {
+        Handshake {
+            name: name.into(),
+            request: request.into(),
+            done: done.into(),
+            argument: None,
+            result: None,
+            error: None,
+            timeout: None,
+        }
+    }
+
+    pub fn with_argument(mut self, tag: &str, value: SPValue) -> Handshake {
+        self.argument = Some((tag.into(), value));
+        self
+    }
+
+    /// Failure codes are the other values in the domain of the tag.
+    pub fn with_result(mut self, tag: &str, ok: SPValue) -> Handshake {
+        self.result = Some((tag.into(), ok));
+        self
+    }
+
+    /// The PLC raises `error` when it gives up, and takes it down
+    /// with the request.
+    pub fn with_error(mut self, error: &str) -> Handshake {
+        self.error = Some(error.into());
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Handshake {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// What the PLC watchdog needs to time the handshake out.
+    pub fn timer(&self, tags: &[PlcTag], keys: &dyn TagKeys) -> Option<HandshakeTimer> {
+        self.timeout.map(|timeout| HandshakeTimer {
+            name: self.name.clone(),
+            request_node: tag_key(tags, keys, &self.request),
+            done_node: tag_key(tags, keys, &self.done),
+            timeout,
+        })
+    }
+}
+
+/// What an operation needs to run a handshake. Either the goal or
+/// `failed` eventually holds while the request is up.
+pub struct PlcHandshake {
+    pub goal: Predicate,
+    pub failed: Predicate,
+    pub actions: Vec<Action>,
+    pub initial_state: SPState,
+}
 
 pub struct PLCResource {
     pub path: SPPath,
-    pub bool_from_plc_1: SPPath,
-    pub bool_from_plc_2: SPPath,
-    pub bool_from_plc_3: SPPath,
-    pub bool_from_plc_4: SPPath,
-    pub bool_from_plc_5: SPPath,
-    pub int_from_plc_1: SPPath,
-    pub int_from_plc_2: SPPath,
-    pub int_from_plc_3: SPPath,
-    pub int_from_plc_4: SPPath,
-    pub int_from_plc_5: SPPath,
-    pub bool_to_plc_1: SPPath,
-    pub bool_to_plc_2: SPPath,
-    pub bool_to_plc_3: SPPath,
-    pub bool_to_plc_4: SPPath,
-    pub bool_to_plc_5: SPPath,
-    pub int_to_plc_1: SPPath,
-    pub int_to_plc_2: SPPath,
-    pub int_to_plc_3: SPPath,
-    pub int_to_plc_4: SPPath,
-    pub int_to_plc_5: SPPath,
+    pub tags: HashMap<String, SPPath>,
+    pub connected: SPPath, // Measured. published by the watchdog from message age
     pub initial_state: SPState,
+    interface: RosInterface,
+    domains: HashMap<String, Vec<SPValue>>,
+    directions: HashMap<String, PlcDirection>,
+    // (tag, state) -> (value, predicate) for enumerated tags
+    states: HashMap<(String, String), (i32, SPPath)>,
 }
 
+impl PLCResource {
+    pub fn new(resource: &mut Resource, interface: &RosInterface, keys: &dyn TagKeys,
+               tags: &[PlcTag]) -> PLCResource {
+        let name = resource.path().leaf();
+
+        let mut paths = HashMap::new();
+        let mut states = HashMap::new();
+        let mut to_plc = Vec::new();
+        let mut from_plc = Vec::new();
+        let mut initial_values = Vec::new();
+        let mut domains = HashMap::new();
+        let mut directions = HashMap::new();
+        let mut idle = Vec::new();
 
+        for tag in tags {
+            domains.insert(tag.name.clone(), tag.domain.clone());
+            directions.insert(tag.name.clone(), tag.direction);
+            let path = resource.add_variable(tag.variable());
+            let mv = MessageVariable::new(&path, &keys.key(tag));
+            match tag.direction {
+                PlcDirection::ToPlc => {
+                    to_plc.push(mv);
+                    idle.push(tag.idle(&path));
+                }
+                PlcDirection::FromPlc => from_plc.push(mv),
+            }
+            initial_values.push((path.clone(), tag.initial_value()));
 
-impl PLCResource {
-    pub fn new(resource: &mut Resource, int_from_domain: [Vec<SPValue>; 5], int_to_domain: [Vec<SPValue>; 5]) -> PLCResource {
-        let _name = resource.path().leaf();
-        let bool_to_plc_1= resource.add_variable(Variable::new_boolean("command/bool_to_plc_1", VariableType::Command));
-        let bool_to_plc_2= resource.add_variable(Variable::new_boolean("command/bool_to_plc_2", VariableType::Command));
-        let bool_to_plc_3= resource.add_variable(Variable::new_boolean("command/bool_to_plc_3", VariableType::Command));
-        let bool_to_plc_4= resource.add_variable(Variable::new_boolean("command/bool_to_plc_4", VariableType::Command));
-        let bool_to_plc_5= resource.add_variable(Variable::new_boolean("command/bool_to_plc_5", VariableType::Command));
-        let int_to_plc_1= resource.add_variable(Variable::new("command/int_to_plc_1", VariableType::Command, SPValueType::Int32, int_to_domain[0].clone()));
-        let int_to_plc_2= resource.add_variable(Variable::new("command/int_to_plc_2", VariableType::Command, SPValueType::Int32, int_to_domain[1].clone()));
-        let int_to_plc_3= resource.add_variable(Variable::new("command/int_to_plc_3", VariableType::Command, SPValueType::Int32, int_to_domain[2].clone()));
-        let int_to_plc_4= resource.add_variable(Variable::new("command/int_to_plc_4", VariableType::Command, SPValueType::Int32, int_to_domain[3].clone()));
-        let int_to_plc_5= resource.add_variable(Variable::new("command/int_to_plc_5", VariableType::Command, SPValueType::Int32, int_to_domain[4].clone()));
-        let bool_from_plc_1= resource.add_variable(Variable::new_boolean("measured/bool_from_plc_1", VariableType::Measured));
-        let bool_from_plc_2= resource.add_variable(Variable::new_boolean("measured/bool_from_plc_2", VariableType::Measured));
-        let bool_from_plc_3= resource.add_variable(Variable::new_boolean("measured/bool_from_plc_3", VariableType::Measured));
-        let bool_from_plc_4= resource.add_variable(Variable::new_boolean("measured/bool_from_plc_4", VariableType::Measured));
-        let bool_from_plc_5= resource.add_variable(Variable::new_boolean("measured/bool_from_plc_5", VariableType::Measured));
-        let int_from_plc_1= resource.add_variable(Variable::new("measured/int_from_plc_1", VariableType::Measured, SPValueType::Int32, int_from_domain[0].clone()));
-        let int_from_plc_2= resource.add_variable(Variable::new("measured/int_from_plc_2", VariableType::Measured, SPValueType::Int32, int_from_domain[1].clone()));
-        let int_from_plc_3= resource.add_variable(Variable::new("measured/int_from_plc_3", VariableType::Measured, SPValueType::Int32, int_from_domain[2].clone()));
-        let int_from_plc_4= resource.add_variable(Variable::new("measured/int_from_plc_4", VariableType::Measured, SPValueType::Int32, int_from_domain[3].clone()));
-        let int_from_plc_5= resource.add_variable(Variable::new("measured/int_from_plc_5", VariableType::Measured, SPValueType::Int32, int_from_domain[4].clone()));
-
-        resource.setup_ros_outgoing("command", "/opc_command", MessageType::Json,
-            &[
-                MessageVariable::new(&bool_to_plc_1, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.bool_to_plc_1"),
-                MessageVariable::new(&bool_to_plc_2, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.bool_to_plc_2"),
-                MessageVariable::new(&bool_to_plc_3, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.bool_to_plc_3"),
-                MessageVariable::new(&bool_to_plc_4, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.bool_to_plc_4"),
-                MessageVariable::new(&bool_to_plc_5, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.bool_to_plc_5"),
-                MessageVariable::new(&int_to_plc_1, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.int_to_plc_1"),
-                MessageVariable::new(&int_to_plc_2, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.int_to_plc_2"),
-                MessageVariable::new(&int_to_plc_3, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.int_to_plc_3"),
-                MessageVariable::new(&int_to_plc_4, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.int_to_plc_4"),
-                MessageVariable::new(&int_to_plc_5, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.int_to_plc_5"),
-            ]
-        );
-        resource.setup_ros_incoming("measured", "/opc_measured", MessageType::Json,
+            for (value, state) in &tag.states {
+                let is_state = Variable::new_predicate(&format!("{}_is_{}", tag.name, state),
+                                                       p!(path == value));
+                let is_state = resource.add_variable(is_state);
+                states.insert((tag.name.clone(), state.clone()), (*value, is_state));
+            }
+
+            if paths.insert(tag.name.clone(), path).is_some() {
+                panic!("PLC tag {} is defined twice", tag.name);
+            }
+        }
+
+        resource.setup_ros_outgoing("command", &interface.resolve("/opc_command"), MessageType::Json,
+                                    &to_plc);
+        resource.setup_ros_incoming("measured", &interface.resolve("/opc_measured"), MessageType::Json,
+                                    &from_plc);
+
+        // see crate::watchdog
+        let connected = resource.add_variable(
+            Variable::new_boolean("measured/plc_connected", VariableType::Measured));
+        resource.setup_ros_incoming("connected", &interface.resolve("/opc_connected"),
+                                    MessageType::Ros("std_msgs/msg/Bool".into()),
             &[
-                MessageVariable::new(&bool_from_plc_1, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.bool_from_plc_1"),
-                MessageVariable::new(&bool_from_plc_2, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.bool_from_plc_2"),
-                MessageVariable::new(&bool_from_plc_3, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.bool_from_plc_3"),
-                MessageVariable::new(&bool_from_plc_4, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.bool_from_plc_4"),
-                MessageVariable::new(&bool_from_plc_5, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.bool_from_plc_5"),
-                MessageVariable::new(&int_from_plc_1, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.int_from_plc_1"),
-                MessageVariable::new(&int_from_plc_2, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.int_from_plc_2"),
-                MessageVariable::new(&int_from_plc_3, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.int_from_plc_3"),
-                MessageVariable::new(&int_from_plc_4, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.int_from_plc_4"),
-                MessageVariable::new(&int_from_plc_5, "ns=4;s=|var|CODESYS CONTROL FOR Raspberry Pi MC SL.Application.IO.int_from_plc_5"),
-            ]
-        );
-
-        let initial_state =  SPState::new_from_values(&[
-            (bool_from_plc_1.clone(), SPValue::Bool(false)),
-            (bool_from_plc_2.clone(), SPValue::Bool(false)),
-            (bool_from_plc_3.clone(), SPValue::Bool(false)),
-            (bool_from_plc_4.clone(), SPValue::Bool(false)),
-            (bool_from_plc_5.clone(), SPValue::Bool(false)),
-            (int_from_plc_1.clone(), int_from_domain[0][0].clone()),
-            (int_from_plc_2.clone(), int_from_domain[1][0].clone()),
-            (int_from_plc_3.clone(), int_from_domain[2][0].clone()),
-            (int_from_plc_4.clone(), int_from_domain[3][0].clone()),
-            (int_from_plc_5.clone(), int_from_domain[4][0].clone()),
-            (bool_to_plc_1.clone(), SPValue::Bool(false)),
-            (bool_to_plc_2.clone(), SPValue::Bool(false)),
-            (bool_to_plc_3.clone(), SPValue::Bool(false)),
-            (bool_to_plc_4.clone(), SPValue::Bool(false)),
-            (bool_to_plc_5.clone(), SPValue::Bool(false)),
-            (int_to_plc_1.clone(), int_to_domain[0][0].clone()),
-            (int_to_plc_2.clone(), int_to_domain[1][0].clone()),
-            (int_to_plc_3.clone(), int_to_domain[2][0].clone()),
-            (int_to_plc_4.clone(), int_to_domain[3][0].clone()),
-            (int_to_plc_5.clone(), int_to_domain[4][0].clone()),
-        ]);
+                MessageVariable::new(&connected, "data"),
+            ]);
+        initial_values.push((connected.clone(), false.to_spvalue()));
+
+        // formal representation of the link coming and going. losing
+        // it takes all commands down at once, the runner does that
+        // reset itself, see add_connection_invariants.
+        let all_idle = Predicate::AND(idle.iter().map(|(p, _)| p.clone()).collect());
+        let not_idle = Predicate::NOT(Box::new(all_idle));
+        let to_idle: Vec<Action> = idle.iter().map(|(_, a)| a.clone()).collect();
+        resource.add_transition(Transition::new(
+            &format!("{}_link_up", name),
+            p!(!connected),
+            Predicate::TRUE,
+            vec![a!(connected)],
+            vec![],
+            TransitionType::Effect));
+        let mut link_down = vec![a!(!connected)];
+        link_down.extend(to_idle.iter().cloned());
+        resource.add_transition(Transition::new(
+            &format!("{}_link_down", name),
+            p!(connected),
+            Predicate::TRUE,
+            link_down,
+            vec![],
+            TransitionType::Effect));
+        resource.add_transition(Transition::new(
+            &format!("{}_link_lost", name),
+            p!([!connected] && [p: not_idle]),
+            Predicate::TRUE,
+            to_idle,
+            vec![],
+            TransitionType::Runner));
 
         PLCResource {
             path: resource.path().clone(),
-            bool_from_plc_1,
-            bool_from_plc_2,
-            bool_from_plc_3,
-            bool_from_plc_4,
-            bool_from_plc_5,
-            int_from_plc_1,
-            int_from_plc_2,
-            int_from_plc_3,
-            int_from_plc_4,
-            int_from_plc_5,
-            bool_to_plc_1,
-            bool_to_plc_2,
-            bool_to_plc_3,
-            bool_to_plc_4,
-            bool_to_plc_5,
-            int_to_plc_1,
-            int_to_plc_2,
-            int_to_plc_3,
-            int_to_plc_4,
-            int_to_plc_5,
-            initial_state,
+            tags: paths,
+            connected,
+            initial_state: SPState::new_from_values(&initial_values),
+            interface: interface.clone(),
+            domains,
+            directions,
+            states,
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> SPPath {
+        self.tags.get(name).cloned()
+            .unwrap_or_else(|| panic!("{} has no tag {}", self.path, name))
+    }
+
+    /// Predicate that is true when an enumerated tag is in `state`.
+    pub fn state(&self, tag: &str, state: &str) -> SPPath {
+        self.state_entry(tag, state).1.clone()
+    }
+
+    fn state_entry(&self, tag: &str, state: &str) -> &(i32, SPPath) {
+        self.states.get(&(tag.to_string(), state.to_string()))
+            .unwrap_or_else(|| panic!("{} tag {} has no state {}", self.path, tag, state))
+    }
+
+    /// Tell the formal model how the PLC moves an enumerated tag
+    /// between states. Each (from, to, guard) becomes an effect, so
+    /// the planner can reason about the sequence inside the PLC.
+    /// Only tags the PLC writes can be moved by it.
+    pub fn add_state_machine(&self, model: &mut Model, tag: &str,
+                             transitions: &[(&str, &str, Predicate)]) {
+        let path = self.tag(tag);
+        if self.directions.get(tag) != Some(&PlcDirection::FromPlc) {
+            panic!("{} tag {} is not written by the PLC", self.path, tag);
+        }
+        let r = model.get_resource(&self.path);
+        let name = r.path().leaf();
+        for (from, to, guard) in transitions {
+            let from_value = self.state_entry(tag, from).0;
+            let to_value = self.state_entry(tag, to).0;
+            let guard = guard.clone();
+            r.add_transition(Transition::new(
+                &format!("{}_{}_{}_to_{}", name, tag, from, to),
+                p!([path == from_value] && [p: guard]),
+                Predicate::TRUE,
+                vec![a!(path <- to_value)],
+                vec![],
+                TransitionType::Effect));
         }
     }
+
+    /// Generate the command, effect, reset and failure transitions
+    /// for a handshake. The returned goal and actions plug directly
+    /// into an operation. When the exchange `failed` the operation
+    /// never reaches its goal, take the request down and reset it.
+    pub fn add_handshake(&self, model: &mut Model, h: &Handshake) -> PlcHandshake {
+        let request = self.tag(&h.request);
+        let done = self.tag(&h.done);
+        let connected = self.connected.clone();
+
+        let mut request_actions = vec![a!(request)];
+        if let Some((tag, value)) = &h.argument {
+            let argument = self.tag(tag);
+            request_actions.push(a!(argument <- value));
+        }
+
+        let r = model.get_resource(&self.path);
+        let name = r.path().leaf();
+
+        let mut success = vec![p!(request), p!(done)];
+        let mut failures = vec![];
+        let mut initial_values = vec![];
+
+        r.add_transition(Transition::new(
+            &format!("{}_{}_request", name, h.name),
+            p!([connected] && [!request] && [!done]),
+            Predicate::TRUE,
+            request_actions,
+            vec![],
+            TransitionType::Controlled));
+
+        // one done effect per possible result, so that failure codes
+        // are part of the formal model.
+        match &h.result {
+            None => r.add_transition(Transition::new(
+                &format!("{}_{}_done", name, h.name),
+                p!([request] && [!done]),
+                Predicate::TRUE,
+                vec![a!(done)],
+                vec![],
+                TransitionType::Effect)),
+            Some((tag, ok)) => {
+                let result = self.tag(tag);
+                let mut values = self.domains.get(tag).cloned().unwrap_or_default();
+                if !values.contains(ok) {
+                    values.insert(0, ok.clone());
+                }
+                for value in &values {
+                    r.add_transition(Transition::new(
+                        &format!("{}_{}_done_{}", name, h.name, value),
+                        p!([request] && [!done]),
+                        Predicate::TRUE,
+                        vec![a!(done), a!(result <- value)],
+                        vec![],
+                        TransitionType::Effect));
+                }
+                success.push(p!(result == ok));
+                failures.push(p!([done] && [result != ok]));
+            }
+        }
+
+        // the plc takes done down when it sees the request go low.
+        r.add_transition(Transition::new(
+            &format!("{}_{}_ack_reset", name, h.name),
+            p!([!request] && [done]),
+            Predicate::TRUE,
+            vec![a!(!done)],
+            vec![],
+            TransitionType::Effect));
+
+        if let Some(tag) = &h.error {
+            let error = self.tag(tag);
+            r.add_transition(Transition::new(
+                &format!("{}_{}_error", name, h.name),
+                p!([request] && [!done] && [!error]),
+                Predicate::TRUE,
+                vec![a!(error)],
+                vec![],
+                TransitionType::Effect));
+            r.add_transition(Transition::new(
+                &format!("{}_{}_error_reset", name, h.name),
+                p!([!request] && [error]),
+                Predicate::TRUE,
+                vec![a!(!error)],
+                vec![],
+                TransitionType::Effect));
+            failures.push(p!(error));
+        }
+
+        if h.timeout.is_some() {
+            let timed_out = r.add_variable(Variable::new_boolean(
+                &format!("measured/{}_timed_out", h.name), VariableType::Measured));
+            r.setup_ros_incoming(&format!("{}_timed_out", h.name),
+                                 &self.interface.resolve(&format!("/opc_timeout/{}", h.name)),
+                                 MessageType::Ros("std_msgs/msg/Bool".into()),
+                &[
+                    MessageVariable::new(&timed_out, "data"),
+                ]);
+            r.add_transition(Transition::new(
+                &format!("{}_{}_timeout", name, h.name),
+                p!([request] && [!done] && [!timed_out]),
+                Predicate::TRUE,
+                vec![a!(timed_out)],
+                vec![],
+                TransitionType::Effect));
+            r.add_transition(Transition::new(
+                &format!("{}_{}_timeout_reset", name, h.name),
+                p!([!request] && [timed_out]),
+                Predicate::TRUE,
+                vec![a!(!timed_out)],
+                vec![],
+                TransitionType::Effect));
+            initial_values.push((timed_out.clone(), false.to_spvalue()));
+            failures.push(p!([!done] && [timed_out]));
+        }
+
+        let failed = if failures.is_empty() {
+            Predicate::FALSE
+        } else {
+            let failures = Predicate::OR(failures);
+            p!([request] && [p: failures])
+        };
+        PlcHandshake {
+            goal: Predicate::AND(success),
+            failed,
+            actions: vec![a!(!request)],
+            initial_state: SPState::new_from_values(&initial_values),
+        }
+    }
+
+    /// Forbid raising any command to the PLC while the link is down.
+    /// Everything driven through the PLC waits until it is back.
+    /// Non-bool commands must stay at their initial value. Losing the
+    /// link takes the commands down, see `new`.
+    pub fn add_connection_invariants(&self, model: &mut Model, tags: &[PlcTag]) {
+        let connected = &self.connected;
+        for tag in tags {
+            if tag.direction != PlcDirection::ToPlc {
+                continue;
+            }
+            let (idle, _) = tag.idle(&self.tag(&tag.name));
+            model.add_invar(
+                &format!("{}_{}_needs_connection", self.path.leaf(), tag.name),
+                &p!([!connected] => [p: idle]),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const STATES: &[(i32, &str)] = &[(0, "idle"), (1, "running")];
+
+    fn make_plc(m: &mut Model) -> PLCResource {
+        let tags = [
+            PlcTag::bool_to_plc("start", "bool_to_plc_1"),
+            PlcTag::enumerated("program", PlcDirection::FromPlc, "int_from_plc_1", STATES),
+            PlcTag::enumerated("mode", PlcDirection::ToPlc, "int_to_plc_1", STATES),
+        ];
+        let r = m.add_resource("plc");
+        PLCResource::new(m.get_resource(&r), &RosInterface::default(),
+                         &OpcNodeIds::default(), &tags)
+    }
+
+    #[test]
+    fn state_machine_adds_effects() {
+        let mut m = Model::new("test");
+        let plc = make_plc(&mut m);
+        let start = plc.tag("start");
+        let before = m.get_resource(&plc.path).transitions.len();
+        plc.add_state_machine(&mut m, "program", &[
+            ("idle", "running", p!(start)),
+            ("running", "idle", Predicate::TRUE),
+        ]);
+        let r = m.get_resource(&plc.path);
+        assert_eq!(r.transitions.len(), before + 2);
+        assert!(r.transitions[before..].iter().all(|t| t.type_ == TransitionType::Effect));
+    }
+
+    #[test]
+    fn losing_the_link_takes_commands_down() {
+        let mut m = Model::new("test");
+        let plc = make_plc(&mut m);
+        let r = m.get_resource(&plc.path);
+        let down = r.transitions.iter().find(|t| t.path().leaf() == "plc_link_down").unwrap();
+        // connected, start and mode
+        assert_eq!(down.actions().len(), 3);
+        let lost = r.transitions.iter().find(|t| t.path().leaf() == "plc_link_lost").unwrap();
+        assert_eq!(lost.type_, TransitionType::Runner);
+        assert!(r.transitions.iter().any(|t| t.path().leaf() == "plc_link_up"));
+    }
+
+    #[test]
+    #[should_panic(expected = "not written by the PLC")]
+    fn state_machine_on_command_panics() {
+        let mut m = Model::new("test");
+        let plc = make_plc(&mut m);
+        plc.add_state_machine(&mut m, "mode", &[("idle", "running", Predicate::TRUE)]);
+    }
 }
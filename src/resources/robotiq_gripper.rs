@@ -1,10 +1,16 @@
 use sp_domain::*;
+use super::gripper::Gripper;
+use super::interface::RosInterface;
 
 pub struct RobotiqGripper {
     pub path: SPPath,
     pub measured: SPPath, // "opened", "closed", "gripping"
+    pub part_expected: SPPath, // Command. set when we close to grip a part
     pub is_closing: SPPath, // predicates
     pub is_opening: SPPath,
+    pub part_detected: SPPath, // closed on a part
+    pub released: SPPath,
+    pub part_missing: SPPath, // closed empty when we expected a part
     pub initial_state: SPState,
 }
 
@@ -14,16 +20,24 @@ pub struct RobotiqGripper {
 // The state machine (no error state for now):
 //
 // command == open && measured != open -> measured = open
-// command == close && (measured == open) -> measured = closed
-// command == close && (measured == open) -> measured = gripping
+// command == close && !part_expected && (measured == open) -> measured = closed
+// command == close && part_expected && (measured == open) -> measured = gripping
+// command == close && part_expected && (measured == open) -> measured = closed (missed)
 //
+// The closed outcome is what the gripper reports when no object was
+// detected. When that happens while we expected a part, part_missing
+// becomes true, which scenarios can react on to correct the product
+// state and replan (e.g. retry the pick). The missed effect keeps
+// this outcome in the formal model so that verification and planning
+// see the recovery too.
 //
 impl RobotiqGripper {
-    pub fn new(r: &mut Resource) -> RobotiqGripper {
+    pub fn new(r: &mut Resource, interface: &RosInterface) -> RobotiqGripper {
         let _name = r.path().leaf();
 
         let open_trigger = r.add_variable(Variable::new_boolean("open_trigger", VariableType::Command));
         let close_trigger = r.add_variable(Variable::new_boolean("close_trigger", VariableType::Command));
+        let part_expected = r.add_variable(Variable::new_boolean("part_expected", VariableType::Command));
 
         let measured = r.add_variable(Variable::new("measured", VariableType::Measured,
                                                            SPValueType::String,
@@ -35,17 +49,17 @@ impl RobotiqGripper {
         // setup the service
         let open_service = r.setup_ros_service(
             "robotiq_2f_open",
-            "/robotiq_2f_open",
+            &interface.resolve("/robotiq_2f_open"),
             "std_srvs/srv/Trigger",
             p!(open_trigger), &[], &[]);
 
         let close_service = r.setup_ros_service(
             "robotiq_2f_close",
-            "/robotiq_2f_close",
+            &interface.resolve("/robotiq_2f_close"),
             "std_srvs/srv/Trigger",
             p!(close_trigger), &[], &[]);
 
-        r.setup_ros_incoming("measured", "/robotiq_2f_measured",
+        r.setup_ros_incoming("measured", &interface.resolve("/robotiq_2f_measured"),
                                     MessageType::Ros("robotiq_2f_msgs/msg/MeasuredState".into()),
             &[
                 MessageVariable::new(&measured, "measured"),
@@ -75,17 +89,27 @@ impl RobotiqGripper {
             vec![],
             TransitionType::Auto));
 
+        // close without expecting anything between the fingers.
         r.add_transition(Transition::new(
             &format!("{}_close", &r.path().leaf()),
             p!([close_service == "ok"] && [!close_trigger] && [measured == "opened"]),
             Predicate::TRUE,
-            vec![a!(close_trigger)],
+            vec![a!(close_trigger), a!(!part_expected)],
+            vec![],
+            TransitionType::Controlled));
+
+        // close expecting to grip a part.
+        r.add_transition(Transition::new(
+            &format!("{}_grip", &r.path().leaf()),
+            p!([close_service == "ok"] && [!close_trigger] && [measured == "opened"]),
+            Predicate::TRUE,
+            vec![a!(close_trigger), a!(part_expected)],
             vec![],
             TransitionType::Controlled));
 
         r.add_transition(Transition::new(
             &format!("{}_close_empty_done", &r.path().leaf()),
-            p!([close_trigger] && [measured != "gripping"] && [measured != "closed"]),
+            p!([close_trigger] && [!part_expected] && [measured != "gripping"] && [measured != "closed"]),
             Predicate::TRUE,
             vec![a!(measured <- "closed")],
             vec![],
@@ -93,12 +117,20 @@ impl RobotiqGripper {
 
         r.add_transition(Transition::new(
                 &format!("{}_close_gripping_done", &r.path().leaf()),
-                p!([close_trigger] && [measured != "gripping"] && [measured != "closed"]),
+                p!([close_trigger] && [part_expected] && [measured != "gripping"] && [measured != "closed"]),
                 Predicate::TRUE,
                 vec![a!(measured <- "gripping")],
                 vec![],
                 TransitionType::Effect));
 
+        r.add_transition(Transition::new(
+                &format!("{}_close_missed", &r.path().leaf()),
+                p!([close_trigger] && [part_expected] && [measured != "gripping"] && [measured != "closed"]),
+                Predicate::TRUE,
+                vec![a!(measured <- "closed")],
+                vec![],
+                TransitionType::Effect));
+
         r.add_transition(Transition::new(
             &format!("{}_close_reset", &r.path().leaf()),
             p!([close_trigger] && [[measured == "gripping"] || [measured == "closed"]]),
@@ -117,19 +149,50 @@ impl RobotiqGripper {
                                                                   [measured != "opened"]));
         let is_opening = r.add_variable(is_opening);
 
+        // the driver derives "gripping" from the object detection
+        // status of the gripper (fingers stopped on an object while
+        // closing), so this is the object detection measurement. a
+        // part slipping out later is not seen until the next command.
+        let part_detected = Variable::new_predicate("part_detected", p!(measured == "gripping"));
+        let part_detected = r.add_variable(part_detected);
+
+        let released = Variable::new_predicate("released", p!(measured == "opened"));
+        let released = r.add_variable(released);
+
+        // the failure outcome. the gripper closed all the way
+        // even though the operation told us a part should be there.
+        let part_missing = Variable::new_predicate("part_missing", p!([part_expected] &&
+                                                                      [measured == "closed"]));
+        let part_missing = r.add_variable(part_missing);
+
         let initial_state = SPState::new_from_values(
             &[
                 (open_trigger.clone(), false.to_spvalue()),
                 (close_trigger.clone(), false.to_spvalue()),
+                (part_expected.clone(), false.to_spvalue()),
             ]);
 
         return RobotiqGripper {
             path: r.path().clone(),
             measured,
+            part_expected,
             is_closing,
             is_opening,
+            part_detected,
+            released,
+            part_missing,
             initial_state,
         }
     }
+}
 
+impl Gripper for RobotiqGripper {
+    fn path(&self) -> &SPPath { &self.path }
+    fn measured(&self) -> &SPPath { &self.measured }
+    fn is_closing(&self) -> &SPPath { &self.is_closing }
+    fn is_opening(&self) -> &SPPath { &self.is_opening }
+    fn holding_part(&self) -> &SPPath { &self.part_detected }
+    fn released(&self) -> &SPPath { &self.released }
+    fn part_missing(&self) -> &SPPath { &self.part_missing }
+    fn initial_state(&self) -> &SPState { &self.initial_state }
 }
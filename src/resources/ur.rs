@@ -1,4 +1,13 @@
 use sp_domain::*;
+use super::interface::RosInterface;
+
+/// A motion added with `define_motion`, kept for documentation.
+pub struct Motion {
+    pub operation: String,
+    pub guard: Predicate, // the operation guard
+    pub tcp_frame: String,
+    pub goal_frame: String,
+}
 
 pub struct UrRobotResource {
     pub path: SPPath,
@@ -16,6 +25,7 @@ pub struct UrRobotResource {
     pub error: SPPath,  // Measured. Error from action
     pub action_state: SPPath,
     pub initial_state: SPState,
+    pub motions: Vec<Motion>,
     t_index: i32,
 }
 
@@ -32,7 +42,7 @@ pub struct UrRobotResource {
 //
 
 impl UrRobotResource {
-    pub fn new(model: &mut Model, path: &SPPath, mut frame_domain: Vec<SPValue>, tool_frame_domain: Vec<SPValue>) -> UrRobotResource {
+    pub fn new(model: &mut Model, path: &SPPath, interface: &RosInterface, mut frame_domain: Vec<SPValue>, tool_frame_domain: Vec<SPValue>) -> UrRobotResource {
         frame_domain.insert(0, "unknown".to_spvalue());
 
         // add robot state to high level model
@@ -82,7 +92,7 @@ impl UrRobotResource {
 
         let action_state = resource.setup_ros_action(
             "URControl",
-            "/ur_control",
+            &interface.resolve("/ur_control"),
             "ur_controller_msgs/action/URControl",
             p!(trigger),
             // goal variables
@@ -226,6 +236,7 @@ impl UrRobotResource {
             error,
             action_state,
             initial_state,
+            motions: vec![],
             t_index: 0,
         }
     }
@@ -250,7 +261,8 @@ impl UrRobotResource {
                             [(self.goal_feature_name) == goal_frame]);
 
         // Add high level operation for the motion
-        model.add_op(&format!("move_to_{}_with_{}_{}", goal_frame, tcp_frame, self.t_index),
+        let operation = format!("move_to_{}_with_{}_{}", goal_frame, tcp_frame, self.t_index);
+        model.add_op(&operation,
                      &operation_guard,
                      &vec![a!((self.last_visited_frame) <- goal_frame),
                            a!((self.last_visited_with_tcp) <- tcp_frame),],
@@ -304,6 +316,12 @@ impl UrRobotResource {
             vec![],
             TransitionType::Controlled));
 
+        self.motions.push(Motion {
+            operation,
+            guard: operation_guard,
+            tcp_frame: tcp_frame.into(),
+            goal_frame: goal_frame.into(),
+        });
         self.t_index += 1;
 
     }
@@ -0,0 +1,166 @@
+use sp_domain::*;
+use super::gripper::Gripper;
+use super::interface::RosInterface;
+use crate::watchdog::VacuumWatchdog;
+use std::time::Duration;
+
+pub struct VacuumGripper {
+    pub path: SPPath,
+    pub suction: SPPath, // Command. suction on/off
+    pub vacuum_ok: SPPath, // Measured. vacuum sensor, true when a part is held
+    pub timed_out: SPPath, // Measured. suction on for too long without vacuum
+    pub is_closing: SPPath, // predicates
+    pub is_opening: SPPath,
+    pub released: SPPath,
+    pub holding_part: SPPath,
+    pub part_missing: SPPath,
+    pub initial_state: SPState,
+}
+
+// The state machine:
+//
+// suction && !vacuum_ok -> vacuum_ok
+// suction && !vacuum_ok -> timed_out (missed)
+// !suction && vacuum_ok -> !vacuum_ok
+// !suction && timed_out -> !timed_out
+//
+// Without a part under the cup the vacuum never builds up. The
+// timeout comes from `watchdog::run_vacuum_watchdog`, after which
+// the part counts as missing until suction is turned off again.
+// Suction is not turned on again before the timeout is reset. The
+// watchdog is not started by the resource, whoever launches a model
+// with a vacuum gripper must run it, see `VacuumGripper::watchdog`.
+//
+impl VacuumGripper {
+    pub fn new(r: &mut Resource, interface: &RosInterface) -> VacuumGripper {
+        let _name = r.path().leaf();
+
+        let suction = r.add_variable(Variable::new_boolean("suction", VariableType::Command));
+        let vacuum_ok = r.add_variable(Variable::new_boolean("vacuum_ok", VariableType::Measured));
+        let timed_out = r.add_variable(Variable::new_boolean("timed_out", VariableType::Measured));
+
+        r.setup_ros_outgoing("suction", &interface.resolve("/vacuum_suction"),
+                             MessageType::Ros("std_msgs/msg/Bool".into()),
+            &[
+                MessageVariable::new(&suction, "data"),
+            ]);
+
+        r.setup_ros_incoming("vacuum_ok", &interface.resolve("/vacuum_ok"),
+                             MessageType::Ros("std_msgs/msg/Bool".into()),
+            &[
+                MessageVariable::new(&vacuum_ok, "data"),
+            ]);
+
+        r.setup_ros_incoming("timed_out", &interface.resolve("/vacuum_timeout"),
+                             MessageType::Ros("std_msgs/msg/Bool".into()),
+            &[
+                MessageVariable::new(&timed_out, "data"),
+            ]);
+
+        r.add_transition(Transition::new(
+            &format!("{}_suction_on", &r.path().leaf()),
+            p!([!suction] && [!vacuum_ok] && [!timed_out]),
+            Predicate::TRUE,
+            vec![a!(suction)],
+            vec![],
+            TransitionType::Controlled));
+
+        r.add_transition(Transition::new(
+            &format!("{}_suction_off", &r.path().leaf()),
+            p!(suction),
+            Predicate::TRUE,
+            vec![a!(!suction)],
+            vec![],
+            TransitionType::Controlled));
+
+        r.add_transition(Transition::new(
+            &format!("{}_vacuum_built", &r.path().leaf()),
+            p!([suction] && [!vacuum_ok]),
+            Predicate::TRUE,
+            vec![a!(vacuum_ok)],
+            vec![],
+            TransitionType::Effect));
+
+        r.add_transition(Transition::new(
+            &format!("{}_vacuum_released", &r.path().leaf()),
+            p!([!suction] && [vacuum_ok]),
+            Predicate::TRUE,
+            vec![a!(!vacuum_ok)],
+            vec![],
+            TransitionType::Effect));
+
+        r.add_transition(Transition::new(
+            &format!("{}_vacuum_missed", &r.path().leaf()),
+            p!([suction] && [!vacuum_ok] && [!timed_out]),
+            Predicate::TRUE,
+            vec![a!(timed_out)],
+            vec![],
+            TransitionType::Effect));
+
+        r.add_transition(Transition::new(
+            &format!("{}_timeout_reset", &r.path().leaf()),
+            p!([!suction] && [timed_out]),
+            Predicate::TRUE,
+            vec![a!(!timed_out)],
+            vec![],
+            TransitionType::Effect));
+
+        let is_closing = Variable::new_predicate("is_closing", p!([suction] && [!vacuum_ok] && [!timed_out]));
+        let is_closing = r.add_variable(is_closing);
+
+        let is_opening = Variable::new_predicate("is_opening", p!([!suction] && [vacuum_ok]));
+        let is_opening = r.add_variable(is_opening);
+
+        let released = Variable::new_predicate("released", p!([!suction] && [!vacuum_ok]));
+        let released = r.add_variable(released);
+
+        let holding_part = Variable::new_predicate("holding_part", p!([suction] && [vacuum_ok]));
+        let holding_part = r.add_variable(holding_part);
+
+        let part_missing = Variable::new_predicate("part_missing", p!([suction] && [!vacuum_ok] && [timed_out]));
+        let part_missing = r.add_variable(part_missing);
+
+        let initial_state = SPState::new_from_values(
+            &[
+                (suction.clone(), false.to_spvalue()),
+                (timed_out.clone(), false.to_spvalue()),
+            ]);
+
+        return VacuumGripper {
+            path: r.path().clone(),
+            suction,
+            vacuum_ok,
+            timed_out,
+            is_closing,
+            is_opening,
+            released,
+            holding_part,
+            part_missing,
+            initial_state,
+        }
+    }
+
+    /// The watchdog publishing the timeout, on the topics of a
+    /// gripper created with `interface`. Run it next to the runner:
+    ///
+    /// tokio::spawn(run_vacuum_watchdog(VacuumGripper::watchdog(&interface, timeout)));
+    pub fn watchdog(interface: &RosInterface, timeout: Duration) -> VacuumWatchdog {
+        VacuumWatchdog {
+            suction_topic: interface.resolve("/vacuum_suction"),
+            vacuum_topic: interface.resolve("/vacuum_ok"),
+            timeout_topic: interface.resolve("/vacuum_timeout"),
+            timeout,
+        }
+    }
+}
+
+impl Gripper for VacuumGripper {
+    fn path(&self) -> &SPPath { &self.path }
+    fn measured(&self) -> &SPPath { &self.vacuum_ok }
+    fn is_closing(&self) -> &SPPath { &self.is_closing }
+    fn is_opening(&self) -> &SPPath { &self.is_opening }
+    fn holding_part(&self) -> &SPPath { &self.holding_part }
+    fn released(&self) -> &SPPath { &self.released }
+    fn part_missing(&self) -> &SPPath { &self.part_missing }
+    fn initial_state(&self) -> &SPState { &self.initial_state }
+}
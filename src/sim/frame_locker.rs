@@ -0,0 +1,86 @@
+use crate::resources::interface::RosInterface;
+use futures::StreamExt;
+use r2r::scene_manipulation_msgs::srv::LookupTransform;
+use r2r::std_srvs::srv::Trigger;
+use sp_domain::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Fake frame locker. All frames exist from the start, and are
+/// locked and unlocked together through the Trigger services like
+/// the real node. Lookups always find a pose stamped now.
+#[derive(Debug, Clone, Default)]
+pub struct SimFrameLocker {
+    pub frame_ids: Vec<String>,
+}
+
+fn serve_lock(node: &mut r2r::Node, service: &str, locked: Vec<Arc<Mutex<bool>>>, value: bool) -> SPResult<()> {
+    let mut requests = node
+        .create_service::<Trigger::Service>(service)
+        .map_err(SPError::from_any)?;
+    tokio::spawn(async move {
+        while let Some(req) = requests.next().await {
+            for l in &locked {
+                *l.lock().unwrap() = value;
+            }
+            let _ = req.respond(Trigger::Response {
+                success: true,
+                message: "".into(),
+            });
+        }
+    });
+    Ok(())
+}
+
+pub fn start(node: &mut r2r::Node, interface: &RosInterface, config: SimFrameLocker) -> SPResult<()> {
+    let mut frames = HashMap::new();
+    for id in &config.frame_ids {
+        let locked = Arc::new(Mutex::new(false));
+
+        let exists = node
+            .create_publisher::<r2r::std_msgs::msg::Bool>(
+                &interface.resolve(&format!("/frame_exists/{}", id)), r2r::QosProfile::default())
+            .map_err(SPError::from_any)?;
+        let locked_publisher = node
+            .create_publisher::<r2r::std_msgs::msg::Bool>(
+                &interface.resolve(&format!("/frame_locked/{}", id)), r2r::QosProfile::default())
+            .map_err(SPError::from_any)?;
+        frames.insert(id.clone(), (locked, exists, locked_publisher));
+    }
+
+    let all: Vec<_> = frames.values().map(|(locked, _, _)| locked.clone()).collect();
+    serve_lock(node, &interface.resolve("/lock_frames"), all.clone(), true)?;
+    serve_lock(node, &interface.resolve("/unlock_frames"), all, false)?;
+
+    let mut lookups = node
+        .create_service::<LookupTransform::Service>(&interface.resolve("/lookup_transform"))
+        .map_err(SPError::from_any)?;
+    tokio::spawn(async move {
+        while let Some(req) = lookups.next().await {
+            let mut response = LookupTransform::Response {
+                success: true,
+                ..Default::default()
+            };
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            response.transform.header.stamp.sec = now.as_secs() as i32;
+            response.transform.header.stamp.nanosec = now.subsec_nanos();
+            response.transform.header.frame_id = req.message.parent_frame_id.clone();
+            response.transform.child_frame_id = req.message.child_frame_id.clone();
+            let _ = req.respond(response);
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(100));
+        loop {
+            interval.tick().await;
+            for (locked, exists, locked_publisher) in frames.values() {
+                let locked = *locked.lock().unwrap();
+                let _ = exists.publish(&r2r::std_msgs::msg::Bool { data: true });
+                let _ = locked_publisher.publish(&r2r::std_msgs::msg::Bool { data: locked });
+            }
+        }
+    });
+    Ok(())
+}
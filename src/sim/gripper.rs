@@ -0,0 +1,74 @@
+use crate::resources::interface::RosInterface;
+use futures::StreamExt;
+use r2r::robotiq_2f_msgs::msg::MeasuredState;
+use r2r::std_srvs::srv::Trigger;
+use rand::Rng;
+use sp_domain::*;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Fake Robotiq 2F services. Closing ends in "gripping" with
+/// probability `part_rate`, otherwise in "closed".
+#[derive(Debug, Clone)]
+pub struct SimGripper {
+    pub duration: Duration,
+    pub part_rate: f64,
+}
+
+impl Default for SimGripper {
+    fn default() -> Self {
+        SimGripper {
+            duration: Duration::from_millis(500),
+            part_rate: 1.0,
+        }
+    }
+}
+
+fn serve(node: &mut r2r::Node, service: &str, measured: Arc<Mutex<String>>,
+         outcome: impl Fn() -> String + Send + 'static, duration: Duration) -> SPResult<()> {
+    let mut requests = node
+        .create_service::<Trigger::Service>(service)
+        .map_err(SPError::from_any)?;
+    tokio::spawn(async move {
+        while let Some(req) = requests.next().await {
+            *measured.lock().unwrap() = "unknown".into();
+            let _ = req.respond(Trigger::Response {
+                success: true,
+                message: "".into(),
+            });
+            tokio::time::sleep(duration).await;
+            *measured.lock().unwrap() = outcome();
+        }
+    });
+    Ok(())
+}
+
+pub fn start(node: &mut r2r::Node, interface: &RosInterface, config: SimGripper) -> SPResult<()> {
+    let measured = Arc::new(Mutex::new("opened".to_string()));
+
+    serve(node, &interface.resolve("/robotiq_2f_open"), measured.clone(),
+          || "opened".into(), config.duration)?;
+    let part_rate = config.part_rate;
+    serve(node, &interface.resolve("/robotiq_2f_close"), measured.clone(),
+          move || if rand::thread_rng().gen_bool(part_rate) { "gripping".into() } else { "closed".into() },
+          config.duration)?;
+
+    let publisher = node
+        .create_publisher::<MeasuredState>(&interface.resolve("/robotiq_2f_measured"),
+                                           r2r::QosProfile::default())
+        .map_err(SPError::from_any)?;
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(100));
+        loop {
+            interval.tick().await;
+            let msg = MeasuredState {
+                measured: measured.lock().unwrap().clone(),
+                ..Default::default()
+            };
+            if publisher.publish(&msg).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
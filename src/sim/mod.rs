@@ -0,0 +1,48 @@
+//! In-process stand-ins for the hardware behind `crate::resources`,
+//! for dry runs of a model on a single machine. Start them with
+//! `run_simulation` next to the runner.
+
+pub mod ur;
+pub mod gripper;
+pub mod plc;
+pub mod frame_locker;
+pub mod vacuum_gripper;
+pub mod modbus;
+
+use crate::resources::interface::RosInterface;
+use sp_domain::*;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default)]
+pub struct SimConfig {
+    pub ur: ur::SimUr,
+    pub gripper: gripper::SimGripper,
+    pub plc: plc::SimPlc,
+    pub frame_locker: frame_locker::SimFrameLocker,
+    pub vacuum_gripper: Option<vacuum_gripper::SimVacuumGripper>,
+    pub modbus: Option<modbus::SimModbus>,
+}
+
+/// Start all simulated resources on one node and spin it.
+/// Runs until the node fails.
+pub async fn run_simulation(interface: &RosInterface, config: SimConfig) -> SPResult<()> {
+    let ctx = r2r::Context::create().map_err(SPError::from_any)?;
+    let mut node = r2r::Node::create(ctx, "sp_simulation", "").map_err(SPError::from_any)?;
+
+    ur::start(&mut node, interface, config.ur)?;
+    gripper::start(&mut node, interface, config.gripper)?;
+    plc::start(&mut node, interface, config.plc)?;
+    frame_locker::start(&mut node, interface, config.frame_locker)?;
+    if let Some(vacuum_gripper) = config.vacuum_gripper {
+        vacuum_gripper::start(&mut node, interface, vacuum_gripper)?;
+    }
+    if let Some(modbus) = config.modbus {
+        modbus::start(modbus)?;
+    }
+
+    tokio::task::spawn_blocking(move || loop {
+        node.spin_once(Duration::from_millis(10));
+    })
+    .await
+    .map_err(SPError::from_any)
+}
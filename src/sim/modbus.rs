@@ -0,0 +1,115 @@
+use crate::modbus::{encode_bits, encode_frame, READ_COILS, READ_HOLDING_REGISTERS,
+                    WRITE_SINGLE_COIL, WRITE_SINGLE_REGISTER};
+use crate::resources::modbus::ModbusRegister;
+use sp_domain::*;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Fake Modbus TCP server for `modbus::run_modbus_bridge`. Like
+/// `plc::SimPlc`, a value written to the first register of an echo
+/// pair shows up in the second one after `delay`.
+#[derive(Debug, Clone)]
+pub struct SimModbus {
+    pub address: String,
+    pub delay: Duration,
+    pub echo: Vec<(ModbusRegister, ModbusRegister)>,
+}
+
+impl Default for SimModbus {
+    fn default() -> Self {
+        SimModbus {
+            address: "127.0.0.1:5020".into(),
+            delay: Duration::from_secs(2),
+            echo: vec![],
+        }
+    }
+}
+
+type Registers = Arc<Mutex<HashMap<ModbusRegister, u16>>>;
+
+pub fn start(config: SimModbus) -> SPResult<()> {
+    let listener = std::net::TcpListener::bind(&config.address).map_err(SPError::from_any)?;
+    listener.set_nonblocking(true).map_err(SPError::from_any)?;
+    let listener = TcpListener::from_std(listener).map_err(SPError::from_any)?;
+
+    let registers: Registers = Arc::new(Mutex::new(HashMap::new()));
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let (registers, config) = (registers.clone(), config.clone());
+            tokio::spawn(async move {
+                // the bridge reconnects if we drop it.
+                let _ = serve(socket, registers, config).await;
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn serve(mut socket: TcpStream, registers: Registers, config: SimModbus) -> io::Result<()> {
+    loop {
+        let mut header = [0u8; 7];
+        socket.read_exact(&mut header).await?;
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        if length < 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty modbus request"));
+        }
+        let mut pdu = vec![0u8; length - 1];
+        socket.read_exact(&mut pdu).await?;
+
+        let response = respond(&pdu, &registers, &config);
+        let transaction = u16::from_be_bytes([header[0], header[1]]);
+        socket.write_all(&encode_frame(transaction, header[6], &response)).await?;
+    }
+}
+
+fn write(registers: &Registers, config: &SimModbus, register: ModbusRegister, value: u16) {
+    registers.lock().unwrap().insert(register, value);
+    for (_, to) in config.echo.iter().filter(|(from, _)| *from == register) {
+        let (registers, to, delay) = (registers.clone(), *to, config.delay);
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            registers.lock().unwrap().insert(to, value);
+        });
+    }
+}
+
+fn respond(pdu: &[u8], registers: &Registers, config: &SimModbus) -> Vec<u8> {
+    if pdu.len() < 5 {
+        return vec![pdu[0] | 0x80, 3]; // illegal data value
+    }
+    let address = u16::from_be_bytes([pdu[1], pdu[2]]);
+    let value = u16::from_be_bytes([pdu[3], pdu[4]]);
+    let read = |register: ModbusRegister| registers.lock().unwrap().get(&register).cloned().unwrap_or(0);
+
+    match pdu[0] {
+        READ_COILS => {
+            let bits: Vec<bool> = (0..value)
+                .map(|i| read(ModbusRegister::Coil(address.wrapping_add(i))) != 0)
+                .collect();
+            let bytes = encode_bits(&bits);
+            let mut response = vec![READ_COILS, bytes.len() as u8];
+            response.extend(bytes);
+            response
+        }
+        READ_HOLDING_REGISTERS => {
+            let mut response = vec![READ_HOLDING_REGISTERS, (value * 2) as u8];
+            for i in 0..value {
+                response.extend_from_slice(&read(ModbusRegister::HoldingRegister(address.wrapping_add(i))).to_be_bytes());
+            }
+            response
+        }
+        WRITE_SINGLE_COIL => {
+            write(registers, config, ModbusRegister::Coil(address), (value == 0xFF00) as u16);
+            pdu.to_vec()
+        }
+        WRITE_SINGLE_REGISTER => {
+            write(registers, config, ModbusRegister::HoldingRegister(address), value);
+            pdu.to_vec()
+        }
+        function => vec![function | 0x80, 1], // illegal function
+    }
+}
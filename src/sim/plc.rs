@@ -0,0 +1,99 @@
+use crate::resources::interface::RosInterface;
+use crate::resources::plc::{tag_key, Handshake, PlcTag, TagKeys};
+use futures::StreamExt;
+use sp_domain::*;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Fake PLC behind the OPC json topics. After `delay`, the value of
+/// each command node is written back to its paired measured node.
+/// Handshakes thereby complete, and reset when the request drops.
+/// Sensors follow `latch`: when the command node turns true, the
+/// measured node gets the value after `delay`.
+#[derive(Debug, Clone)]
+pub struct SimPlc {
+    pub delay: Duration,
+    pub echo: Vec<(String, String)>, // (command node id, measured node id)
+    pub latch: Vec<(String, String, serde_json::Value)>, // (command node id, measured node id, value)
+}
+
+impl Default for SimPlc {
+    fn default() -> Self {
+        SimPlc {
+            delay: Duration::from_secs(2),
+            echo: vec![],
+            latch: vec![],
+        }
+    }
+}
+
+impl SimPlc {
+    /// Answer each handshake by echoing its request to its done tag.
+    pub fn answering(tags: &[PlcTag], keys: &dyn TagKeys, handshakes: &[Handshake]) -> SimPlc {
+        SimPlc {
+            echo: handshakes.iter()
+                .map(|h| (tag_key(tags, keys, &h.request), tag_key(tags, keys, &h.done)))
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+fn set_later(m: &Arc<Mutex<serde_json::Map<String, serde_json::Value>>>, node: &str,
+             value: &serde_json::Value, delay: Duration) {
+    let (m, node, value) = (m.clone(), node.to_string(), value.clone());
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        m.lock().unwrap().insert(node, value);
+    });
+}
+
+pub fn start(node: &mut r2r::Node, interface: &RosInterface, config: SimPlc) -> SPResult<()> {
+    let mut commands = node
+        .subscribe::<r2r::std_msgs::msg::String>(&interface.resolve("/opc_command"),
+                                                 r2r::QosProfile::default())
+        .map_err(SPError::from_any)?;
+    let publisher = node
+        .create_publisher::<r2r::std_msgs::msg::String>(&interface.resolve("/opc_measured"),
+                                                        r2r::QosProfile::default())
+        .map_err(SPError::from_any)?;
+
+    let measured = Arc::new(Mutex::new(serde_json::Map::new()));
+
+    let m = measured.clone();
+    tokio::spawn(async move {
+        let mut previous = serde_json::Value::Null;
+        while let Some(msg) = commands.next().await {
+            let command: serde_json::Value = match serde_json::from_str(&msg.data) {
+                Ok(c) => c,
+                Err(_) => continue,
+            };
+            for (from, to) in &config.echo {
+                if let Some(v) = command.get(from) {
+                    set_later(&m, to, v, config.delay);
+                }
+            }
+            let is_true = |c: &serde_json::Value, node: &str| c.get(node).and_then(|v| v.as_bool()) == Some(true);
+            for (from, to, v) in &config.latch {
+                if is_true(&command, from) && !is_true(&previous, from) {
+                    set_later(&m, to, v, config.delay);
+                }
+            }
+            previous = command;
+        }
+    });
+
+    // publish continuously like the real bridge, which keeps the
+    // watchdog happy.
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(100));
+        loop {
+            interval.tick().await;
+            let data = serde_json::Value::Object(measured.lock().unwrap().clone()).to_string();
+            if publisher.publish(&r2r::std_msgs::msg::String { data }).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
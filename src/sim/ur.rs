@@ -0,0 +1,57 @@
+use crate::resources::interface::RosInterface;
+use futures::StreamExt;
+use r2r::ur_controller_msgs::action::URControl;
+use rand::Rng;
+use sp_domain::*;
+use std::time::Duration;
+
+/// Fake URControl action server. Every goal takes `duration` and
+/// fails with probability `failure_rate`.
+#[derive(Debug, Clone)]
+pub struct SimUr {
+    pub duration: Duration,
+    pub failure_rate: f64,
+}
+
+impl Default for SimUr {
+    fn default() -> Self {
+        SimUr {
+            duration: Duration::from_secs(1),
+            failure_rate: 0.0,
+        }
+    }
+}
+
+pub fn start(node: &mut r2r::Node, interface: &RosInterface, config: SimUr) -> SPResult<()> {
+    let mut requests = node
+        .create_action_server::<URControl::Action>(&interface.resolve("/ur_control"))
+        .map_err(SPError::from_any)?;
+
+    tokio::spawn(async move {
+        while let Some(req) = requests.next().await {
+            let frame = req.goal.goal_feature_id.clone();
+            let (mut goal, _cancel) = match req.accept() {
+                Ok(g) => g,
+                Err(e) => {
+                    println!("sim ur: could not accept goal: {}", e);
+                    continue;
+                }
+            };
+            let config = config.clone();
+            tokio::spawn(async move {
+                let _ = goal.publish_feedback(URControl::Feedback {
+                    current_state: format!("moving to {}", frame),
+                    ..Default::default()
+                });
+                tokio::time::sleep(config.duration).await;
+                let success = !rand::thread_rng().gen_bool(config.failure_rate);
+                let result = URControl::Result {
+                    success,
+                    ..Default::default()
+                };
+                let _ = if success { goal.succeed(result) } else { goal.abort(result) };
+            });
+        }
+    });
+    Ok(())
+}
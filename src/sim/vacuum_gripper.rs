@@ -0,0 +1,80 @@
+use crate::resources::interface::RosInterface;
+use futures::StreamExt;
+use rand::Rng;
+use sp_domain::*;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Fake vacuum gripper. When suction turns on, vacuum builds up
+/// after `duration` with probability `part_rate`. The vacuum
+/// timeout comes from `watchdog::run_vacuum_watchdog` as in the lab.
+#[derive(Debug, Clone)]
+pub struct SimVacuumGripper {
+    pub duration: Duration,
+    pub part_rate: f64,
+}
+
+impl Default for SimVacuumGripper {
+    fn default() -> Self {
+        SimVacuumGripper {
+            duration: Duration::from_millis(300),
+            part_rate: 1.0,
+        }
+    }
+}
+
+pub fn start(node: &mut r2r::Node, interface: &RosInterface, config: SimVacuumGripper) -> SPResult<()> {
+    let mut suction = node
+        .subscribe::<r2r::std_msgs::msg::Bool>(&interface.resolve("/vacuum_suction"),
+                                               r2r::QosProfile::default())
+        .map_err(SPError::from_any)?;
+    let publisher = node
+        .create_publisher::<r2r::std_msgs::msg::Bool>(&interface.resolve("/vacuum_ok"),
+                                                      r2r::QosProfile::default())
+        .map_err(SPError::from_any)?;
+
+    // (times suction was turned on, vacuum), so that a late vacuum
+    // does not show up after suction was turned off again.
+    let vacuum_ok = Arc::new(Mutex::new((0u64, false)));
+
+    let v = vacuum_ok.clone();
+    tokio::spawn(async move {
+        let mut on = false;
+        while let Some(msg) = suction.next().await {
+            if msg.data == on {
+                continue;
+            }
+            on = msg.data;
+            let generation = {
+                let mut v = v.lock().unwrap();
+                v.0 += 1;
+                v.1 = false;
+                v.0
+            };
+            if !on {
+                continue;
+            }
+            let part = rand::thread_rng().gen_bool(config.part_rate);
+            let (v, duration) = (v.clone(), config.duration);
+            tokio::spawn(async move {
+                tokio::time::sleep(duration).await;
+                let mut v = v.lock().unwrap();
+                if v.0 == generation {
+                    v.1 = part;
+                }
+            });
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(100));
+        loop {
+            interval.tick().await;
+            let data = vacuum_ok.lock().unwrap().1;
+            if publisher.publish(&r2r::std_msgs::msg::Bool { data }).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
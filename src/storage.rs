@@ -0,0 +1,140 @@
+use crate::parts::PartTracker;
+use crate::resources::gripper::Gripper;
+use crate::resources::ur::UrRobotResource;
+use sp_domain::*;
+
+pub struct StorageSlot {
+    pub location: String, // part location, see crate::parts
+    pub above_frame: String,
+    pub at_frame: String,
+}
+
+/// A rows x columns grid of storage slots holding one part each.
+///
+/// Slots are numbered from 1 row by row. Slot n is the part location
+/// "<name>_n" with the frames "<frame_prefix>n_above" and
+/// "<frame_prefix>n_down".
+pub struct StorageGrid {
+    pub name: String,
+    pub rows: usize,
+    pub columns: usize,
+    pub slots: Vec<StorageSlot>,
+}
+
+impl StorageGrid {
+    pub fn new(name: &str, frame_prefix: &str, rows: usize, columns: usize) -> StorageGrid {
+        let slots = (1..=rows * columns)
+            .map(|n| StorageSlot {
+                location: format!("{}_{}", name, n),
+                above_frame: format!("{}{}_above", frame_prefix, n),
+                at_frame: format!("{}{}_down", frame_prefix, n),
+            })
+            .collect();
+        StorageGrid {
+            name: name.into(),
+            rows,
+            columns,
+            slots,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.rows * self.columns
+    }
+
+    /// The slot at `row` and `column`, both counted from 1 like the
+    /// slot numbers.
+    pub fn slot(&self, row: usize, column: usize) -> &StorageSlot {
+        if row < 1 || row > self.rows || column < 1 || column > self.columns {
+            panic!("{} has no slot at row {} column {}, it is {}x{}",
+                   self.name, row, column, self.rows, self.columns);
+        }
+        &self.slots[(row - 1) * self.columns + (column - 1)]
+    }
+
+    pub fn locations(&self) -> Vec<&str> {
+        self.slots.iter().map(|s| s.location.as_str()).collect()
+    }
+
+    /// The frames the robot moves to, to add to its frame domain.
+    pub fn at_frames(&self) -> Vec<SPValue> {
+        self.slots.iter().map(|s| s.at_frame.to_spvalue()).collect()
+    }
+
+    /// True when the robot was last at one of the slots.
+    pub fn at_any_slot(&self, est_pos: &SPPath) -> Predicate {
+        Predicate::OR(self.slots.iter().map(|s| {
+            let at = &s.at_frame;
+            p!(est_pos == at)
+        }).collect())
+    }
+
+    pub fn is_full(&self, parts: &PartTracker) -> Predicate {
+        Predicate::AND(self.slots.iter().map(|s| parts.is_occupied(&s.location)).collect())
+    }
+
+    pub fn is_empty(&self, parts: &PartTracker) -> Predicate {
+        Predicate::AND(self.slots.iter().map(|s| parts.is_free(&s.location)).collect())
+    }
+
+    /// Motions from `home` down into each slot and back. `enter_guard`
+    /// is added to the motions into the slots, e.g. to require that
+    /// the grid has been located.
+    pub fn define_motions(&self, ur: &mut UrRobotResource, model: &mut Model, tcp: &str,
+                          home: &str, enter_guard: &Predicate) {
+        let est_pos = ur.last_visited_frame.clone();
+        for s in &self.slots {
+            ur.define_motion(model, p!(est_pos == home), enter_guard.clone(), Predicate::TRUE,
+                             tcp, &s.at_frame, "move_j", 0.4, 0.3, vec![], vec![]);
+            ur.define_motion(model, p!(est_pos == s.at_frame), Predicate::TRUE, Predicate::TRUE,
+                             tcp, home, "move_j", 0.4, 0.3, vec![], vec![]);
+        }
+    }
+
+    /// Motion from any slot to `exit`.
+    pub fn define_exit_motion(&self, ur: &mut UrRobotResource, model: &mut Model, tcp: &str,
+                              exit: &str) {
+        let est_pos = ur.last_visited_frame.clone();
+        ur.define_motion(model, self.at_any_slot(&est_pos), Predicate::TRUE, Predicate::TRUE,
+                         tcp, exit, "move_j", 0.4, 0.3, vec![], vec![]);
+    }
+
+    /// The gripper may only close at `grip_at` or in a slot, and only
+    /// open at `release_at` or in a slot.
+    pub fn add_gripper_invariants(&self, model: &mut Model, gripper: &impl Gripper,
+                                  est_pos: &SPPath, grip_at: &[&str], release_at: &[&str]) {
+        let at_frames = |frames: &[&str]| {
+            let mut at: Vec<Predicate> = frames.iter().map(|f| p!(est_pos == f)).collect();
+            at.push(self.at_any_slot(est_pos));
+            Predicate::OR(at)
+        };
+        let is_closing = gripper.is_closing();
+        let is_opening = gripper.is_opening();
+        let grip_at = at_frames(grip_at);
+        let release_at = at_frames(release_at);
+        model.add_invar(
+            "grip_at_the_right_pos",
+            &p!([is_closing] => [p: grip_at]),
+        );
+        model.add_invar(
+            "release_at_the_right_pos",
+            &p!([is_opening] => [p: release_at]),
+        );
+    }
+
+    /// place_at_<slot> and pick_at_<slot> operations moving `part`
+    /// between `holder` (e.g. "gripper") and the slots.
+    pub fn add_operations(&self, model: &mut Model, parts: &PartTracker, part: &str,
+                          holder: &str, est_pos: &SPPath, guard: &Predicate,
+                          place_goal: &Predicate, pick_goal: &Predicate) {
+        for s in &self.slots {
+            let at = &s.at_frame;
+            let guard = guard.clone();
+            let at_slot = p!([p: guard] && [est_pos == at]);
+            parts.add_move_op(model, &format!("place_at_{}", s.location), part,
+                              holder, &s.location, &at_slot, place_goal, &[]);
+            parts.add_move_op(model, &format!("pick_at_{}", s.location), part,
+                              &s.location, holder, &at_slot, pick_goal, &[]);
+        }
+    }
+}
@@ -0,0 +1,459 @@
+use crate::harness::fire;
+use sp_domain::*;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+/// A property that does not hold, with a trace leading to it.
+#[derive(Debug)]
+pub struct Counterexample {
+    pub property: String,
+    pub trace: Vec<String>, // transition names from the initial state
+}
+
+#[derive(Debug)]
+pub struct VerificationReport {
+    pub states_explored: usize,
+    pub complete: bool, // false if we hit the state limit
+    pub counterexamples: Vec<Counterexample>,
+    pub needs_guard: Vec<Counterexample>, // hold only because the supervisor blocks the last transition
+}
+
+impl VerificationReport {
+    pub fn ok(&self) -> bool {
+        self.counterexamples.is_empty()
+    }
+}
+
+impl fmt::Display for VerificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "explored {} states{}", self.states_explored,
+                 if self.complete { "" } else { " (state limit reached, not complete)" })?;
+        for c in &self.needs_guard {
+            writeln!(f, "{} needs a guard, the supervisor blocks the last of:", c.property)?;
+            for (i, t) in c.trace.iter().enumerate() {
+                writeln!(f, "  {:3}: {}", i, t)?;
+            }
+        }
+        if self.counterexamples.is_empty() {
+            return writeln!(f, "all properties hold");
+        }
+        for c in &self.counterexamples {
+            writeln!(f, "{} is violated by:", c.property)?;
+            for (i, t) in c.trace.iter().enumerate() {
+                writeln!(f, "  {:3}: {}", i, t)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn state_key(state: &SPState) -> String {
+    SPStateJson::from_state_flat(state).to_json().to_string()
+}
+
+/// Transitions that are part of the formal model.
+pub(crate) fn formal_transitions(ts: &TransitionSystemModel) -> Vec<&Transition> {
+    ts.transitions
+        .iter()
+        .filter(|t| t.type_ != TransitionType::Runner)
+        .collect()
+}
+
+/// The reachable part of a transition system, found breadth first.
+/// State 0 is the initial state.
+pub struct StateGraph {
+    pub states: Vec<SPState>,
+    pub complete: bool, // false if we hit the state limit
+    names: Vec<String>,    // of the transitions, by index
+    controlled: Vec<bool>, // the transitions a supervisor may block
+    parents: Vec<Option<(usize, usize)>>,  // (state, transition)
+    successors: Vec<Vec<(usize, usize)>>, // (state, transition)
+}
+
+impl StateGraph {
+    /// Shortest trace of transition names from the initial state.
+    pub fn trace(&self, mut index: usize) -> Vec<String> {
+        let mut trace = Vec::new();
+        while let Some((p, t)) = self.parents[index] {
+            trace.push(self.names[t].clone());
+            index = p;
+        }
+        trace.reverse();
+        trace
+    }
+
+    /// Extend `marked` backwards over the edges taken by transitions
+    /// that `follow` accepts.
+    fn backwards(&self, mut marked: Vec<bool>, follow: impl Fn(usize) -> bool) -> Vec<bool> {
+        let mut predecessors = vec![vec![]; self.states.len()];
+        for (from, succ) in self.successors.iter().enumerate() {
+            for (to, t) in succ.iter().filter(|(_, t)| follow(*t)) {
+                predecessors[*to].push(from);
+            }
+        }
+        let mut queue: VecDeque<usize> = (0..marked.len()).filter(|i| marked[*i]).collect();
+        while let Some(i) = queue.pop_front() {
+            for p in &predecessors[i] {
+                if !marked[*p] {
+                    marked[*p] = true;
+                    queue.push_back(*p);
+                }
+            }
+        }
+        marked
+    }
+
+    /// Indices of the states from which some state satisfying
+    /// `goal` can be reached.
+    pub fn can_reach(&self, goal: &Predicate) -> Vec<bool> {
+        let reach = self.states.iter().map(|s| goal.eval(s)).collect();
+        self.backwards(reach, |_| true)
+    }
+
+    /// States that violate one of `specs`, or from which effects and
+    /// auto transitions alone lead to such a state. No guard on the
+    /// controlled transitions can keep the model out of them.
+    pub fn forbidden(&self, specs: &[&Predicate]) -> Vec<bool> {
+        let bad = self.states.iter().map(|s| specs.iter().any(|spec| !spec.eval(s))).collect();
+        self.backwards(bad, |t| !self.controlled[t])
+    }
+
+    /// The graph as the runner sees it once the invariants are turned
+    /// into guards: controlled transitions into `forbidden` states are
+    /// blocked, and only what is still reachable is kept. The initial
+    /// state must not be forbidden.
+    pub fn supervise(&self, forbidden: &[bool]) -> StateGraph {
+        assert!(!forbidden[0], "the initial state is forbidden");
+        let mut index = vec![None; self.states.len()];
+        index[0] = Some(0);
+        let mut graph = StateGraph {
+            states: vec![self.states[0].clone()],
+            complete: self.complete,
+            names: self.names.clone(),
+            controlled: self.controlled.clone(),
+            parents: vec![None],
+            successors: vec![vec![]],
+        };
+        let mut queue = VecDeque::from(vec![0]);
+        while let Some(from) = queue.pop_front() {
+            let f = index[from].unwrap();
+            for &(to, t) in &self.successors[from] {
+                if self.controlled[t] && forbidden[to] {
+                    continue;
+                }
+                let new = match index[to] {
+                    Some(known) => known,
+                    None => {
+                        let new = graph.states.len();
+                        index[to] = Some(new);
+                        graph.states.push(self.states[to].clone());
+                        graph.parents.push(Some((f, t)));
+                        graph.successors.push(vec![]);
+                        queue.push_back(to);
+                        new
+                    }
+                };
+                graph.successors[f].push((new, t));
+            }
+        }
+        graph
+    }
+
+    /// The first state satisfying `goal` and the shortest trace to it,
+    /// taking only the edges (target, transition) that `follow` accepts.
+    fn search(&self, goal: impl Fn(usize) -> bool,
+              follow: impl Fn(usize, usize) -> bool) -> Option<(usize, Vec<String>)> {
+        let mut parents: Vec<Option<Option<(usize, usize)>>> = vec![None; self.states.len()];
+        parents[0] = Some(None);
+        let mut queue = VecDeque::from(vec![0]);
+        while let Some(i) = queue.pop_front() {
+            if goal(i) {
+                let mut trace = vec![];
+                let mut index = i;
+                while let Some(Some((p, t))) = parents[index] {
+                    trace.push(self.names[t].clone());
+                    index = p;
+                }
+                trace.reverse();
+                return Some((i, trace));
+            }
+            for &(to, t) in self.successors[i].iter().filter(|(to, t)| follow(*to, *t)) {
+                if parents[to].is_none() {
+                    parents[to] = Some(Some((i, t)));
+                    queue.push_back(to);
+                }
+            }
+        }
+        None
+    }
+}
+
+pub fn explore(transitions: &[&Transition], initial: &SPState, max_states: usize) -> StateGraph {
+    let mut visited: HashMap<String, usize> = HashMap::new();
+    let mut graph = StateGraph {
+        states: vec![initial.clone()],
+        complete: true,
+        names: transitions.iter().map(|t| t.path().to_string()).collect(),
+        controlled: transitions.iter().map(|t| t.type_ == TransitionType::Controlled).collect(),
+        parents: vec![None],
+        successors: vec![vec![]],
+    };
+    visited.insert(state_key(initial), 0);
+
+    let mut index = 0;
+    while index < graph.states.len() {
+        for (ti, t) in transitions.iter().enumerate().filter(|(_, t)| t.eval(&graph.states[index])) {
+            let mut next = graph.states[index].clone();
+            if fire(t, &mut next).is_err() {
+                continue;
+            }
+            let key = state_key(&next);
+            if let Some(known) = visited.get(&key) {
+                graph.successors[index].push((*known, ti));
+                continue;
+            }
+            if visited.len() >= max_states {
+                graph.complete = false;
+                continue;
+            }
+            let new = graph.states.len();
+            visited.insert(key, new);
+            graph.states.push(next);
+            graph.parents.push(Some((index, ti)));
+            graph.successors.push(vec![]);
+            graph.successors[index].push((new, ti));
+        }
+        index += 1;
+    }
+    graph
+}
+
+/// Breadth first search over the reachable states of the formal
+/// model, supervised like the runner: the invariants added with
+/// `add_invar` become guards on the controlled transitions. An
+/// invariant that effects or auto transitions can break anyway is
+/// violated, one that only holds because a controlled transition is
+/// blocked needs a guard. Also checks that some transition is enabled
+/// in every supervised state. Traces are the shortest found, one per
+/// property.
+pub fn verify(ts: &TransitionSystemModel, initial: &SPState, max_states: usize) -> VerificationReport {
+    let transitions = formal_transitions(ts);
+    let graph = explore(&transitions, initial, max_states);
+    let specs: Vec<&Predicate> = ts.specs.iter().map(|s| s.invariant()).collect();
+    let forbidden = graph.forbidden(&specs);
+
+    let mut counterexamples: Vec<Counterexample> = Vec::new();
+    let mut needs_guard: Vec<Counterexample> = Vec::new();
+    for spec in &ts.specs {
+        let violated = graph.forbidden(&[spec.invariant()]);
+        if !violated.iter().any(|v| *v) {
+            continue;
+        }
+        let property = spec.path().to_string();
+        if violated[0] {
+            let bad = |i: usize| !spec.invariant().eval(&graph.states[i]);
+            let uncontrolled = |_: usize, t: usize| !graph.controlled[t];
+            counterexamples.push(Counterexample {
+                property,
+                trace: graph.search(bad, uncontrolled).map(|(_, t)| t).unwrap_or_default(),
+            });
+            continue;
+        }
+        // the first place where the supervisor steps in, reached by
+        // supervised moves, and the transition it blocks there.
+        let blocked = |i: usize| graph.successors[i].iter()
+            .find(|(to, t)| graph.controlled[*t] && violated[*to])
+            .map(|(_, t)| graph.names[*t].clone());
+        let supervised = |to: usize, t: usize| !(graph.controlled[t] && forbidden[to]);
+        if let Some((i, mut trace)) = graph.search(|i| blocked(i).is_some(), supervised) {
+            trace.extend(blocked(i));
+            needs_guard.push(Counterexample { property, trace });
+        }
+    }
+
+    let states_explored = graph.states.len();
+    if !forbidden[0] {
+        let supervised = graph.supervise(&forbidden);
+        if let Some(i) = supervised.successors.iter().position(|s| s.is_empty()) {
+            counterexamples.push(Counterexample {
+                property: "deadlock".into(),
+                trace: supervised.trace(i),
+            });
+        }
+    }
+
+    VerificationReport {
+        states_explored,
+        complete: graph.complete,
+        counterexamples,
+        needs_guard,
+    }
+}
+
+/// A reachable state where the model can get stuck.
+#[derive(Debug)]
+pub struct StuckState {
+    pub reason: String,
+    pub trace: Vec<String>,
+    pub state: SPState,
+}
+
+#[derive(Debug)]
+pub struct LivenessReport {
+    pub states_explored: usize,
+    pub complete: bool,
+    pub stuck: Vec<StuckState>,
+}
+
+impl LivenessReport {
+    /// Nothing stuck, and the search covered all reachable states.
+    pub fn ok(&self) -> bool {
+        self.complete && self.stuck.is_empty()
+    }
+}
+
+impl fmt::Display for LivenessReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "explored {} states{}", self.states_explored,
+                 if self.complete { "" } else { " (state limit reached, not complete)" })?;
+        if self.stuck.is_empty() {
+            return writeln!(f, "no stuck states found");
+        }
+        for s in &self.stuck {
+            writeln!(f, "{}, reached by:", s.reason)?;
+            for (i, t) in s.trace.iter().enumerate() {
+                writeln!(f, "  {:3}: {}", i, t)?;
+            }
+            writeln!(f, "  state: {}", SPStateJson::from_state_flat(&s.state).to_json())?;
+        }
+        Ok(())
+    }
+}
+
+/// Operations as the runner executes them: started ("e") when their
+/// precondition holds, finished (back to "i") with their effects when
+/// the goal holds.
+pub(crate) fn operation_transitions(m: &Model) -> Vec<Transition> {
+    let high_level = TransitionSystemModel::from_op(m);
+    let mut transitions = vec![];
+    for o in &m.operations {
+        let state = o.path();
+        let op = match high_level.transitions.iter().find(|t| t.path().leaf() == o.path().leaf()) {
+            Some(op) => op,
+            None => continue,
+        };
+        let pre = op.guard().clone();
+        let goal = o.goal().clone();
+        let mut effects = op.actions().to_vec();
+        effects.push(a!(state <- "i"));
+        transitions.push(Transition::new(
+            &format!("{}_start", o.path().leaf()),
+            p!([state == "i"] && [p: pre]),
+            Predicate::TRUE,
+            vec![a!(state <- "e")],
+            vec![],
+            TransitionType::Controlled));
+        transitions.push(Transition::new(
+            &format!("{}_finish", o.path().leaf()),
+            p!([state == "e"] && [p: goal]),
+            Predicate::TRUE,
+            effects,
+            vec![],
+            TransitionType::Auto));
+    }
+    transitions
+}
+
+/// Look for states where the supervised model gets stuck: nothing is
+/// enabled while an operation is executing (deadlock), transitions
+/// keep firing but an executing operation can never finish
+/// (livelock), or the goal of an intention can no longer be reached.
+/// At most `examples` states are listed per problem. Only a complete
+/// search is conclusive, the unexpanded states of an incomplete one
+/// would all look stuck.
+pub fn check_liveness(m: &Model, initial: &SPState, max_states: usize, examples: usize) -> LivenessReport {
+    let ts = TransitionSystemModel::from(m);
+    let ops = operation_transitions(m);
+    let mut transitions = formal_transitions(&ts);
+    transitions.extend(ops.iter());
+    let explored = explore(&transitions, initial, max_states);
+    let specs: Vec<&Predicate> = ts.specs.iter().map(|s| s.invariant()).collect();
+    let forbidden = explored.forbidden(&specs);
+
+    let mut stuck = vec![];
+    if forbidden[0] {
+        stuck.push(StuckState {
+            reason: "the invariants cannot be kept from the initial state".into(),
+            trace: vec![],
+            state: initial.clone(),
+        });
+        return LivenessReport {
+            states_explored: explored.states.len(),
+            complete: explored.complete,
+            stuck,
+        };
+    }
+    let graph = explored.supervise(&forbidden);
+
+    let executing: Vec<_> = m.operations.iter().map(|o| {
+        let state = o.path();
+        (p!(state == "e"), o.path().leaf())
+    }).collect();
+    let mut deadlocks = 0;
+    for (i, state) in graph.states.iter().enumerate() {
+        if deadlocks >= examples {
+            break;
+        }
+        if !graph.successors[i].is_empty() {
+            continue;
+        }
+        let running: Vec<&str> = executing.iter()
+            .filter(|(e, _)| e.eval(state))
+            .map(|(_, name)| name.as_str())
+            .collect();
+        if !running.is_empty() {
+            deadlocks += 1;
+            stuck.push(StuckState {
+                reason: format!("deadlock while executing {}", running.join(", ")),
+                trace: graph.trace(i),
+                state: state.clone(),
+            });
+        }
+    }
+
+    if graph.complete {
+        for o in &m.operations {
+            let op = o.path();
+            let goal = o.goal().clone();
+            let running = p!(op == "e");
+            let finish = graph.can_reach(&p!([op == "e"] && [p: goal]));
+            let livelocked = graph.states.iter().enumerate()
+                .filter(|(i, s)| running.eval(s) && !finish[*i] && !graph.successors[*i].is_empty())
+                .take(examples);
+            for (i, state) in livelocked {
+                stuck.push(StuckState {
+                    reason: format!("livelock, {} can no longer finish", o.path().leaf()),
+                    trace: graph.trace(i),
+                    state: state.clone(),
+                });
+            }
+        }
+
+        for i in m.intentions.iter().filter(|i| i.goal() != &Predicate::FALSE) {
+            let reach = graph.can_reach(i.goal());
+            for (s, _) in reach.iter().enumerate().filter(|(_, r)| !**r).take(examples) {
+                stuck.push(StuckState {
+                    reason: format!("goal of {} is unreachable", i.path().leaf()),
+                    trace: graph.trace(s),
+                    state: graph.states[s].clone(),
+                });
+            }
+        }
+    }
+
+    LivenessReport {
+        states_explored: explored.states.len(),
+        complete: graph.complete,
+        stuck,
+    }
+}
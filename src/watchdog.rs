@@ -0,0 +1,315 @@
+use futures::StreamExt;
+use sp_domain::*;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Settings for the PLC link watchdog.
+pub struct PlcWatchdog {
+    /// Incoming topic of the OPC bridge, e.g. "/opc_measured".
+    pub measured_topic: String,
+    /// Where we publish the link state, e.g. "/opc_connected".
+    pub connected_topic: String,
+    /// Messages older than this means the link is down.
+    pub timeout: Duration,
+    /// Optional node id of a value the PLC keeps changing. If set,
+    /// the link is also considered down when it stops changing.
+    pub heartbeat: Option<String>,
+    /// Outgoing topic of the OPC bridge, e.g. "/opc_command".
+    pub command_topic: String,
+    /// Handshakes with a timeout publish it on <timeout_topic>/<name>.
+    pub timeout_topic: String,
+    pub handshakes: Vec<HandshakeTimer>,
+}
+
+/// A handshake that times out when the PLC does not answer its
+/// request in time, see `resources::plc::Handshake`.
+#[derive(Debug, Clone)]
+pub struct HandshakeTimer {
+    pub name: String,
+    pub request_node: String,
+    pub done_node: String,
+    pub timeout: Duration,
+}
+
+struct LinkState {
+    last_message: Option<Instant>,
+    last_heartbeat: Option<(serde_json::Value, Instant)>,
+    values: serde_json::Map<String, serde_json::Value>,
+    waiting_since: Vec<Option<Instant>>, // per handshake
+}
+
+fn is_true(values: &serde_json::Map<String, serde_json::Value>, node_id: &str) -> bool {
+    values.get(node_id).and_then(|v| v.as_bool()).unwrap_or(false)
+}
+
+/// Watch the OPC bridge and publish whether the PLC is reachable,
+/// and which handshakes have waited too long for the PLC to answer.
+/// Runs until the node fails.
+pub async fn run_plc_watchdog(config: PlcWatchdog) -> SPResult<()> {
+    let ctx = r2r::Context::create().map_err(SPError::from_any)?;
+    let mut node = r2r::Node::create(ctx, "plc_watchdog", "").map_err(SPError::from_any)?;
+
+    let mut measured = node
+        .subscribe::<r2r::std_msgs::msg::String>(&config.measured_topic, r2r::QosProfile::default())
+        .map_err(SPError::from_any)?;
+    let mut command = node
+        .subscribe::<r2r::std_msgs::msg::String>(&config.command_topic, r2r::QosProfile::default())
+        .map_err(SPError::from_any)?;
+    let publisher = node
+        .create_publisher::<r2r::std_msgs::msg::Bool>(&config.connected_topic, r2r::QosProfile::default())
+        .map_err(SPError::from_any)?;
+    let mut timeout_publishers = vec![];
+    for h in &config.handshakes {
+        let p = node
+            .create_publisher::<r2r::std_msgs::msg::Bool>(
+                &format!("{}/{}", config.timeout_topic, h.name), r2r::QosProfile::default())
+            .map_err(SPError::from_any)?;
+        timeout_publishers.push(p);
+    }
+
+    let state = Arc::new(Mutex::new(LinkState {
+        last_message: None,
+        last_heartbeat: None,
+        values: serde_json::Map::new(),
+        waiting_since: vec![None; config.handshakes.len()],
+    }));
+
+    // commands and measurements both end up in `values`, the
+    // handshake timers need the request and the answer.
+    let s = state.clone();
+    tokio::spawn(async move {
+        while let Some(msg) = command.next().await {
+            if let Ok(serde_json::Value::Object(json)) = serde_json::from_str(&msg.data) {
+                s.lock().unwrap().values.extend(json);
+            }
+        }
+    });
+
+    let s = state.clone();
+    let heartbeat = config.heartbeat.clone();
+    tokio::spawn(async move {
+        while let Some(msg) = measured.next().await {
+            let now = Instant::now();
+            let mut s = s.lock().unwrap();
+            s.last_message = Some(now);
+            let json = serde_json::from_str::<serde_json::Value>(&msg.data).ok();
+            let beat = heartbeat.as_ref().and_then(|node_id| {
+                json.as_ref().and_then(|json| json.get(node_id).cloned())
+            });
+            if let Some(serde_json::Value::Object(json)) = json {
+                s.values.extend(json);
+            }
+            if let Some(beat) = beat {
+                let changed = s.last_heartbeat.as_ref().map(|(v, _)| v != &beat).unwrap_or(true);
+                if changed {
+                    s.last_heartbeat = Some((beat, now));
+                }
+            }
+        }
+    });
+
+    let timeout = config.timeout;
+    let check_heartbeat = config.heartbeat.is_some();
+    let mut interval = tokio::time::interval(Duration::from_millis(100));
+    loop {
+        interval.tick().await;
+        node.spin_once(Duration::from_millis(10));
+
+        let (connected, timed_out) = {
+            let mut s = state.lock().unwrap();
+            let fresh = |t: Option<Instant>| t.map(|t| t.elapsed() < timeout).unwrap_or(false);
+            let connected = fresh(s.last_message) &&
+                (!check_heartbeat || fresh(s.last_heartbeat.as_ref().map(|(_, t)| *t)));
+
+            let mut timed_out = vec![];
+            for (i, h) in config.handshakes.iter().enumerate() {
+                let waiting = is_true(&s.values, &h.request_node) && !is_true(&s.values, &h.done_node);
+                let since = match (waiting, s.waiting_since[i]) {
+                    (false, _) => None,
+                    (true, None) => Some(Instant::now()),
+                    (true, since) => since,
+                };
+                s.waiting_since[i] = since;
+                timed_out.push(since.map(|t| t.elapsed() >= h.timeout).unwrap_or(false));
+            }
+            (connected, timed_out)
+        };
+
+        publisher
+            .publish(&r2r::std_msgs::msg::Bool { data: connected })
+            .map_err(SPError::from_any)?;
+        for (p, data) in timeout_publishers.iter().zip(timed_out) {
+            p.publish(&r2r::std_msgs::msg::Bool { data })
+                .map_err(SPError::from_any)?;
+        }
+    }
+}
+
+/// Settings for the frame age watchdog.
+pub struct FrameWatchdog {
+    /// Scene manipulation lookup service, e.g. "/lookup_transform".
+    pub lookup_service: String,
+    pub parent_frame: String,
+    pub frame_ids: Vec<String>,
+    /// Poses with an older stamp than this are not fresh.
+    pub max_age: Duration,
+    /// Freshness is published on <fresh_topic>/<id> and the parent
+    /// frame on <parent_topic>/<id>.
+    pub fresh_topic: String,
+    pub parent_topic: String,
+}
+
+impl Default for FrameWatchdog {
+    fn default() -> Self {
+        FrameWatchdog {
+            lookup_service: "/lookup_transform".into(),
+            parent_frame: "world".into(),
+            frame_ids: vec![],
+            max_age: Duration::from_secs(1),
+            fresh_topic: "/frame_fresh".into(),
+            parent_topic: "/frame_parent".into(),
+        }
+    }
+}
+
+fn age(stamp: &r2r::builtin_interfaces::msg::Time) -> Duration {
+    let stamp = Duration::new(stamp.sec.max(0) as u64, stamp.nanosec);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    now.checked_sub(stamp).unwrap_or_default()
+}
+
+/// Look the frames up in the tf tree and publish whether their pose
+/// is recent enough, for resources::frame_locker. A frame missing
+/// from the tree is not fresh. Locked frames are not refreshed and
+/// go stale here, the frame locker model treats them as fresh. Runs
+/// until the node fails.
+pub async fn run_frame_watchdog(config: FrameWatchdog) -> SPResult<()> {
+    use r2r::scene_manipulation_msgs::srv::LookupTransform;
+
+    let ctx = r2r::Context::create().map_err(SPError::from_any)?;
+    let mut node = r2r::Node::create(ctx, "frame_watchdog", "").map_err(SPError::from_any)?;
+
+    let client = node
+        .create_client::<LookupTransform::Service>(&config.lookup_service)
+        .map_err(SPError::from_any)?;
+    let mut publishers = vec![];
+    for id in &config.frame_ids {
+        let fresh = node
+            .create_publisher::<r2r::std_msgs::msg::Bool>(
+                &format!("{}/{}", config.fresh_topic, id), r2r::QosProfile::default())
+            .map_err(SPError::from_any)?;
+        let parent = node
+            .create_publisher::<r2r::std_msgs::msg::String>(
+                &format!("{}/{}", config.parent_topic, id), r2r::QosProfile::default())
+            .map_err(SPError::from_any)?;
+        publishers.push((id.clone(), fresh, parent));
+    }
+
+    tokio::task::spawn_blocking(move || loop {
+        node.spin_once(Duration::from_millis(10));
+    });
+
+    let mut interval = tokio::time::interval(Duration::from_millis(200));
+    loop {
+        interval.tick().await;
+        for (id, fresh, parent) in &publishers {
+            let request = LookupTransform::Request {
+                parent_frame_id: config.parent_frame.clone(),
+                child_frame_id: id.clone(),
+                deadline: 100,
+            };
+            let response = match client.request(&request) {
+                Ok(response) => tokio::time::timeout(Duration::from_millis(500), response).await,
+                Err(_) => continue,
+            };
+            let (is_fresh, parent_frame) = match response {
+                Ok(Ok(r)) if r.success =>
+                    (age(&r.transform.header.stamp) < config.max_age, r.transform.header.frame_id),
+                _ => (false, String::new()),
+            };
+            fresh
+                .publish(&r2r::std_msgs::msg::Bool { data: is_fresh })
+                .map_err(SPError::from_any)?;
+            parent
+                .publish(&r2r::std_msgs::msg::String { data: parent_frame })
+                .map_err(SPError::from_any)?;
+        }
+    }
+}
+
+/// Settings for the vacuum gripper timeout.
+pub struct VacuumWatchdog {
+    pub suction_topic: String,
+    pub vacuum_topic: String,
+    /// Where we publish the timeout, e.g. "/vacuum_timeout".
+    pub timeout_topic: String,
+    /// Suction on without vacuum for this long means the part is missing.
+    pub timeout: Duration,
+}
+
+impl Default for VacuumWatchdog {
+    fn default() -> Self {
+        VacuumWatchdog {
+            suction_topic: "/vacuum_suction".into(),
+            vacuum_topic: "/vacuum_ok".into(),
+            timeout_topic: "/vacuum_timeout".into(),
+            timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Publish whether suction has been on for longer than the timeout
+/// without building up vacuum, for resources::vacuum_gripper.
+/// Runs until the node fails.
+pub async fn run_vacuum_watchdog(config: VacuumWatchdog) -> SPResult<()> {
+    let ctx = r2r::Context::create().map_err(SPError::from_any)?;
+    let mut node = r2r::Node::create(ctx, "vacuum_watchdog", "").map_err(SPError::from_any)?;
+
+    let mut suction = node
+        .subscribe::<r2r::std_msgs::msg::Bool>(&config.suction_topic, r2r::QosProfile::default())
+        .map_err(SPError::from_any)?;
+    let mut vacuum = node
+        .subscribe::<r2r::std_msgs::msg::Bool>(&config.vacuum_topic, r2r::QosProfile::default())
+        .map_err(SPError::from_any)?;
+    let publisher = node
+        .create_publisher::<r2r::std_msgs::msg::Bool>(&config.timeout_topic, r2r::QosProfile::default())
+        .map_err(SPError::from_any)?;
+
+    // when suction was turned on, and whether vacuum is ok.
+    let suction_since: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
+    let vacuum_ok = Arc::new(Mutex::new(false));
+
+    let s = suction_since.clone();
+    tokio::spawn(async move {
+        while let Some(msg) = suction.next().await {
+            let mut s = s.lock().unwrap();
+            match (msg.data, s.is_some()) {
+                (true, false) => *s = Some(Instant::now()),
+                (false, _) => *s = None,
+                _ => {}
+            }
+        }
+    });
+    let v = vacuum_ok.clone();
+    tokio::spawn(async move {
+        while let Some(msg) = vacuum.next().await {
+            *v.lock().unwrap() = msg.data;
+        }
+    });
+
+    let mut interval = tokio::time::interval(Duration::from_millis(100));
+    loop {
+        interval.tick().await;
+        node.spin_once(Duration::from_millis(10));
+
+        let timed_out = !*vacuum_ok.lock().unwrap() && suction_since
+            .lock()
+            .unwrap()
+            .map(|t| t.elapsed() > config.timeout)
+            .unwrap_or(false);
+
+        publisher
+            .publish(&r2r::std_msgs::msg::Bool { data: timed_out })
+            .map_err(SPError::from_any)?;
+    }
+}